@@ -0,0 +1,222 @@
+/* Nexo runtime for programs compiled to C. Every value carries its type,
+ * and every operation checks it and reports errors the way the VM does:
+ * a message with the source location on stderr, then exit code 1. */
+
+#include <pthread.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* Calls nested deeper than this are a stack overflow, as in the VM. */
+#define NX_MAX_FRAMES 10000
+
+typedef enum { NX_INT, NX_STRING, NX_VOID } nx_tag;
+
+typedef struct {
+    nx_tag tag;
+    union {
+        int64_t i;
+        const char *s;
+    } as;
+} nx_value;
+
+/* Name of the source file, for error locations. */
+static const char *nx_source;
+static int nx_depth;
+
+static inline nx_value nx_int(int64_t i) {
+    nx_value v;
+    v.tag = NX_INT;
+    v.as.i = i;
+    return v;
+}
+
+static inline nx_value nx_str(const char *s) {
+    nx_value v;
+    v.tag = NX_STRING;
+    v.as.s = s;
+    return v;
+}
+
+static inline nx_value nx_void(void) {
+    nx_value v;
+    v.tag = NX_VOID;
+    v.as.i = 0;
+    return v;
+}
+
+static inline const char *nx_type_name(nx_value v) {
+    switch (v.tag) {
+    case NX_INT: return "int";
+    case NX_STRING: return "string";
+    default: return "void";
+    }
+}
+
+static inline int nx_truthy(nx_value v) {
+    return v.tag == NX_INT && v.as.i != 0;
+}
+
+static void nx_fail(const char *code, const char *message, int line, int column) {
+    fflush(stdout);
+    fprintf(stderr, "error[%s]: %s\n --> %s:%d:%d\n", code, message, nx_source, line, column);
+    exit(1);
+}
+
+/* The operands of `op`, which must both be ints. */
+static inline void nx_ints(nx_value a, nx_value b, const char *op, int line, int column) {
+    if (a.tag != NX_INT || b.tag != NX_INT) {
+        char message[128];
+        snprintf(message, sizeof message, "unsupported operand types for `%s`: %s and %s",
+                 op, nx_type_name(a), nx_type_name(b));
+        nx_fail("E0101", message, line, column);
+    }
+}
+
+static void nx_overflow(int64_t a, const char *op, int64_t b, int line, int column) {
+    char message[128];
+    snprintf(message, sizeof message, "attempt to compute `%lld %s %lld`, which overflows",
+             (long long)a, op, (long long)b);
+    nx_fail("E0104", message, line, column);
+}
+
+static inline nx_value nx_add(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "+", line, column);
+    if (__builtin_add_overflow(a.as.i, b.as.i, &r)) nx_overflow(a.as.i, "+", b.as.i, line, column);
+    return nx_int(r);
+}
+
+static inline nx_value nx_sub(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "-", line, column);
+    if (__builtin_sub_overflow(a.as.i, b.as.i, &r)) nx_overflow(a.as.i, "-", b.as.i, line, column);
+    return nx_int(r);
+}
+
+static inline nx_value nx_mul(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "*", line, column);
+    if (__builtin_mul_overflow(a.as.i, b.as.i, &r)) nx_overflow(a.as.i, "*", b.as.i, line, column);
+    return nx_int(r);
+}
+
+static inline nx_value nx_div(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "/", line, column);
+    if (b.as.i == 0) nx_fail("E0103", "attempt to divide by zero", line, column);
+    if (a.as.i == INT64_MIN && b.as.i == -1) nx_overflow(a.as.i, "/", b.as.i, line, column);
+    return nx_int(a.as.i / b.as.i);
+}
+
+static inline nx_value nx_less(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "<", line, column);
+    return nx_int(a.as.i < b.as.i);
+}
+
+static inline nx_value nx_greater(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, ">", line, column);
+    return nx_int(a.as.i > b.as.i);
+}
+
+static inline nx_value nx_equal(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "==", line, column);
+    return nx_int(a.as.i == b.as.i);
+}
+
+/* Builtins */
+
+static inline nx_value nx_print(nx_value v) {
+    switch (v.tag) {
+    case NX_INT: printf("%lld\n", (long long)v.as.i); break;
+    case NX_STRING: printf("%s\n", v.as.s); break;
+    default: printf("\n"); break;
+    }
+    return nx_void();
+}
+
+static inline nx_value nx_wrapping_add(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "wrapping_add", line, column);
+    return nx_int((int64_t)((uint64_t)a.as.i + (uint64_t)b.as.i));
+}
+
+static inline nx_value nx_wrapping_sub(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "wrapping_sub", line, column);
+    return nx_int((int64_t)((uint64_t)a.as.i - (uint64_t)b.as.i));
+}
+
+static inline nx_value nx_wrapping_mul(nx_value a, nx_value b, int line, int column) {
+    nx_ints(a, b, "wrapping_mul", line, column);
+    return nx_int((int64_t)((uint64_t)a.as.i * (uint64_t)b.as.i));
+}
+
+static inline nx_value nx_saturating_add(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "saturating_add", line, column);
+    if (__builtin_add_overflow(a.as.i, b.as.i, &r)) r = b.as.i < 0 ? INT64_MIN : INT64_MAX;
+    return nx_int(r);
+}
+
+static inline nx_value nx_saturating_sub(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "saturating_sub", line, column);
+    if (__builtin_sub_overflow(a.as.i, b.as.i, &r)) r = b.as.i < 0 ? INT64_MAX : INT64_MIN;
+    return nx_int(r);
+}
+
+static inline nx_value nx_saturating_mul(nx_value a, nx_value b, int line, int column) {
+    int64_t r;
+    nx_ints(a, b, "saturating_mul", line, column);
+    if (__builtin_mul_overflow(a.as.i, b.as.i, &r)) {
+        r = (a.as.i < 0) != (b.as.i < 0) ? INT64_MIN : INT64_MAX;
+    }
+    return nx_int(r);
+}
+
+/* Calls */
+
+static inline void nx_enter(int line, int column) {
+    if (nx_depth >= NX_MAX_FRAMES) {
+        char message[64];
+        snprintf(message, sizeof message, "stack overflow: more than %d nested calls", NX_MAX_FRAMES);
+        nx_fail("E0102", message, line, column);
+    }
+    nx_depth++;
+}
+
+static inline void nx_leave(void) {
+    nx_depth--;
+}
+
+/* Stack a call takes for each variable of its function: twice the
+ * 16-byte value, which is more than a C compiler needs even at -O0. */
+#define NX_BYTES_PER_VALUE 32
+#define NX_BYTES_PER_CALL 256
+
+static nx_value (*nx_main)(void);
+
+static void *nx_main_thread(void *unused) {
+    (void)unused;
+    nx_main();
+    return NULL;
+}
+
+/* Run `main` on a thread with a stack for NX_MAX_FRAMES calls to a
+ * function with `values` variables, the most any function has, so the
+ * call limit is reached before the stack runs out. If the thread cannot
+ * be started, `main` runs on this one. */
+static void nx_run_main(nx_value (*main)(void), size_t values) {
+    size_t size = NX_MAX_FRAMES * (values * NX_BYTES_PER_VALUE + NX_BYTES_PER_CALL) + (1 << 20);
+    pthread_attr_t attr;
+    pthread_t thread;
+
+    /* Some systems only take whole pages */
+    size = (size + 0xffff) & ~(size_t)0xffff;
+    nx_main = main;
+    if (pthread_attr_init(&attr) == 0
+        && pthread_attr_setstacksize(&attr, size) == 0
+        && pthread_create(&thread, &attr, nx_main_thread, NULL) == 0) {
+        pthread_join(thread, NULL);
+    } else {
+        main();
+    }
+}
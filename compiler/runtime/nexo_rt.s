@@ -0,0 +1,178 @@
+# Nexo runtime for programs compiled to x86-64 assembly (System V, GNU as).
+#
+# Values are a tag (0 int, 1 string, 2 void) and a payload: the int itself
+# or a pointer to a NUL-terminated string. Output goes through the C
+# library; errors are reported the way the VM reports them, then the
+# program exits with code 1. The generated program defines `nx_source`,
+# the name of its source file.
+
+    .section .rodata
+.Lnx_int_format:
+    .string "%lld\n"
+.Lnx_empty:
+    .string ""
+.Lnx_int_name:
+    .string "int"
+.Lnx_string_name:
+    .string "string"
+.Lnx_void_name:
+    .string "void"
+.Lnx_error_format:
+    .string "error[%s]: %s\n --> %s:%d:%d\n"
+.Lnx_type_format:
+    .string "error[E0101]: unsupported operand types for `%s`: %s and %s\n --> %s:%d:%d\n"
+.Lnx_overflow_format:
+    .string "error[E0104]: attempt to compute `%lld %s %lld`, which overflows\n --> %s:%d:%d\n"
+
+    .bss
+    .align 8
+# Nexo calls in progress, `main` included
+nx_depth:
+    .zero 8
+
+    .text
+
+# nx_run_main(stack size %rdi): run `f_main` on a stack of its own, big
+# enough for the deepest recursion the call limit allows. If it cannot be
+# mapped, `f_main` runs on the stack it was called on.
+nx_run_main:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %rbx
+    subq $8, %rsp
+    movq %rdi, %rbx
+    movq %rdi, %rsi
+    xorl %edi, %edi
+    movl $3, %edx               # PROT_READ | PROT_WRITE
+    movl $0x4022, %ecx          # MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE
+    movl $-1, %r8d
+    xorl %r9d, %r9d
+    call mmap@PLT
+    cmpq $-1, %rax
+    je 1f
+    # The size is a multiple of 16, so the top keeps the stack aligned
+    leaq (%rax,%rbx), %rsp
+1:
+    call f_main
+    movq -8(%rbp), %rbx
+    leave
+    ret
+
+# nx_print(payload %rdi, tag %rsi)
+nx_print:
+    pushq %rbp
+    movq %rsp, %rbp
+    testq %rsi, %rsi
+    jz 1f
+    cmpq $1, %rsi
+    je 2f
+    leaq .Lnx_empty(%rip), %rdi
+    call puts@PLT
+    jmp 3f
+1:
+    movq %rdi, %rsi
+    leaq .Lnx_int_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    jmp 3f
+2:
+    call puts@PLT
+3:
+    popq %rbp
+    ret
+
+# Name of the type with tag %rdi, in %rax
+nx_type_name:
+    leaq .Lnx_int_name(%rip), %rax
+    testq %rdi, %rdi
+    jz 1f
+    leaq .Lnx_string_name(%rip), %rax
+    cmpq $1, %rdi
+    je 1f
+    leaq .Lnx_void_name(%rip), %rax
+1:
+    ret
+
+# nx_error(code %rdi, message %rsi, line %edx, column %ecx)
+nx_error:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $32, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movl %edx, -20(%rbp)
+    movl %ecx, -24(%rbp)
+    xorl %edi, %edi
+    call fflush@PLT
+    movl -24(%rbp), %eax
+    subq $8, %rsp
+    pushq %rax
+    movl $2, %edi
+    leaq .Lnx_error_format(%rip), %rsi
+    movq -8(%rbp), %rdx
+    movq -16(%rbp), %rcx
+    leaq nx_source(%rip), %r8
+    movl -20(%rbp), %r9d
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+# nx_type_error(operator %rdi, left tag %rsi, right tag %rdx, line %ecx, column %r8d)
+nx_type_error:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $48, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movq %rdx, -24(%rbp)
+    movl %ecx, -28(%rbp)
+    movl %r8d, -32(%rbp)
+    xorl %edi, %edi
+    call fflush@PLT
+    movq -16(%rbp), %rdi
+    call nx_type_name
+    movq %rax, -40(%rbp)
+    movq -24(%rbp), %rdi
+    call nx_type_name
+    movq %rax, %r8
+    movl -32(%rbp), %eax
+    pushq %rax
+    movl -28(%rbp), %eax
+    pushq %rax
+    movl $2, %edi
+    leaq .Lnx_type_format(%rip), %rsi
+    movq -8(%rbp), %rdx
+    movq -40(%rbp), %rcx
+    leaq nx_source(%rip), %r9
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+# nx_overflow_error(left %rdi, operator %rsi, right %rdx, line %ecx, column %r8d)
+nx_overflow_error:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $32, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movq %rdx, -24(%rbp)
+    movl %ecx, -28(%rbp)
+    movl %r8d, -32(%rbp)
+    xorl %edi, %edi
+    call fflush@PLT
+    movl -32(%rbp), %eax
+    pushq %rax
+    movl -28(%rbp), %eax
+    pushq %rax
+    movl $2, %edi
+    leaq .Lnx_overflow_format(%rip), %rsi
+    movq -8(%rbp), %rdx
+    movq -16(%rbp), %rcx
+    movq -24(%rbp), %r8
+    leaq nx_source(%rip), %r9
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
@@ -0,0 +1,32 @@
+use crate::ast::BinOp;
+use crate::diagnostic::{E_DIVISION_BY_ZERO, E_INTEGER_OVERFLOW};
+use crate::runtime_error::RuntimeError;
+
+/// Apply an arithmetic or comparison operator to two ints with Nexo's
+/// semantics, shared by every engine:
+///
+/// - results that do not fit in 64 bits are an error, never wrapped
+/// - `/` by zero is an error
+/// - `/` truncates toward zero
+/// - comparisons produce `1` or `0`
+pub fn apply(op: &BinOp, a: i64, b: i64) -> Result<i64, RuntimeError> {
+    let result = match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div if b == 0 => {
+            return Err(RuntimeError::new(E_DIVISION_BY_ZERO, "attempt to divide by zero"));
+        }
+        BinOp::Div => a.checked_div(b),
+        BinOp::Greater => Some((a > b) as i64),
+        BinOp::Less => Some((a < b) as i64),
+        BinOp::Equal => Some((a == b) as i64),
+    };
+
+    result.ok_or_else(|| {
+        RuntimeError::new(
+            E_INTEGER_OVERFLOW,
+            &format!("attempt to compute `{} {} {}`, which overflows", a, op.symbol(), b),
+        )
+    })
+}
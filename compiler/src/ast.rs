@@ -1,3 +1,5 @@
+use crate::span::Span;
+
 #[derive(Debug, Clone)]
 pub struct Program {
     pub functions: Vec<FunctionDecl>,
@@ -8,15 +10,23 @@ pub struct FunctionDecl {
     pub name: String,
     pub params: Vec<String>,
     pub body: Block,
+    pub span: Span, // `fn` keyword through the parameter list
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Block {
     pub statements: Vec<Stmt>,
 }
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum StmtKind {
     Let {
         name: String,
         value: Expr,
@@ -42,7 +52,13 @@ pub enum Stmt {
 }
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Call {
         name: String,
         args: Vec<Expr>,
@@ -69,3 +85,17 @@ pub enum BinOp {
     Less,
     Equal,
 }
+
+impl BinOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Greater => ">",
+            BinOp::Less => "<",
+            BinOp::Equal => "==",
+        }
+    }
+}
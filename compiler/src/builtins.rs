@@ -0,0 +1,73 @@
+/// Functions provided by the language rather than defined in the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Print,
+
+    // Integer arithmetic for when overflow is intended: `wrapping_*` wraps
+    // around at the bounds of a 64-bit int, `saturating_*` clamps to them.
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    SaturatingAdd,
+    SaturatingSub,
+    SaturatingMul,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 7] = [
+        Builtin::Print,
+        Builtin::WrappingAdd,
+        Builtin::WrappingSub,
+        Builtin::WrappingMul,
+        Builtin::SaturatingAdd,
+        Builtin::SaturatingSub,
+        Builtin::SaturatingMul,
+    ];
+
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Builtin::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    /// Number identifying the builtin in bytecode files: its position in
+    /// `ALL`, so new builtins must be added at the end.
+    pub fn id(self) -> u8 {
+        Builtin::ALL.iter().position(|&b| b == self).unwrap() as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Builtin> {
+        Builtin::ALL.get(id as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::WrappingAdd => "wrapping_add",
+            Builtin::WrappingSub => "wrapping_sub",
+            Builtin::WrappingMul => "wrapping_mul",
+            Builtin::SaturatingAdd => "saturating_add",
+            Builtin::SaturatingSub => "saturating_sub",
+            Builtin::SaturatingMul => "saturating_mul",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Print => 1,
+            _ => 2,
+        }
+    }
+
+    /// Evaluate one of the two-int arithmetic builtins.
+    /// Never called for `print`, which engines implement themselves.
+    pub fn apply_int(self, a: i64, b: i64) -> i64 {
+        match self {
+            Builtin::WrappingAdd => a.wrapping_add(b),
+            Builtin::WrappingSub => a.wrapping_sub(b),
+            Builtin::WrappingMul => a.wrapping_mul(b),
+            Builtin::SaturatingAdd => a.saturating_add(b),
+            Builtin::SaturatingSub => a.saturating_sub(b),
+            Builtin::SaturatingMul => a.saturating_mul(b),
+            Builtin::Print => unreachable!("print is not an arithmetic builtin"),
+        }
+    }
+}
@@ -1,174 +1,215 @@
-use crate::ast::*;
-
+use crate::ast::BinOp;
+use crate::builtins::Builtin;
+use crate::ir::{self, BlockId, Callee, Const, Function, Instruction, Reg, Terminator};
+
+/// Helpers every generated program starts with: tagged values, checked
+/// arithmetic and error reporting that match the VM.
+const RUNTIME: &str = include_str!("../runtime/nexo_rt.h");
+
+/// Translates the IR of a checked program to a self-contained C file.
+///
+/// Nexo names are prefixed so they cannot clash with C keywords or the
+/// runtime (`f_` for functions), and each IR register becomes a C variable
+/// `rN`. Blocks become labels, so control flow is a series of `goto`s
+/// that the C compiler is free to turn back into loops.
 pub struct CodeGenerator {
     output: String,
+    source_name: String,
 }
 
 impl CodeGenerator {
-    pub fn new() -> Self {
+    pub fn new(source_name: &str) -> Self {
         CodeGenerator {
             output: String::new(),
+            source_name: source_name.to_string(),
         }
     }
 
-    pub fn generate(mut self, program: &Program) -> String {
+    pub fn generate(mut self, program: &ir::Program) -> String {
         self.emit_prelude();
 
+        // Forward declarations, so functions can call each other in any order
+        for func in &program.functions {
+            let signature = signature(func);
+            self.line(&format!("{};", signature));
+        }
+        self.output.push('\n');
+
         for func in &program.functions {
             self.emit_function(func);
         }
 
+        let values = program.functions.iter().map(Function::register_count).max();
+        self.emit_main(values.unwrap_or(0));
         self.output
     }
 
     fn emit_prelude(&mut self) {
-        self.output.push_str("#include <stdio.h>\n\n");
+        self.output.push_str(RUNTIME);
+        self.output.push('\n');
     }
 
-    fn emit_function(&mut self, func: &FunctionDecl) {
-        self.output.push_str("int ");
-        self.output.push_str(&func.name);
-        self.output.push('(');
-
-        for (i, param) in func.params.iter().enumerate() {
-            if i > 0 {
-                self.output.push_str(", ");
-            }
-            self.output.push_str("int ");
-            self.output.push_str(param);
-        }
-
-        self.output.push_str(") {\n");
-        self.emit_block(&func.body);
-        self.output.push_str("}\n\n");
+    /// `values` is the most variables any function has, which sizes the
+    /// stack the program runs on.
+    fn emit_main(&mut self, values: usize) {
+        self.line("int main(void) {");
+        let source = c_string(&self.source_name);
+        self.line(&format!("    nx_source = {};", source));
+        // `main`'s own frame counts toward the call limit
+        self.line("    nx_depth = 1;");
+        self.line(&format!("    nx_run_main(f_main, {});", values));
+        self.line("    return 0;");
+        self.line("}");
     }
 
+    fn emit_function(&mut self, func: &Function) {
+        self.line(&format!("{} {{", signature(func)));
 
-    fn emit_block(&mut self, block: &Block) {
-        for stmt in &block.statements {
-            self.emit_stmt(stmt);
+        let locals: Vec<String> = (0..func.register_count())
+            .map(Reg)
+            .filter(|r| !func.params.contains(r))
+            .map(|r| format!("r{}", r.0))
+            .collect();
+        if !locals.is_empty() {
+            self.line(&format!("    nx_value {};", locals.join(", ")));
         }
-    }
 
-    fn emit_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Let { name, value } => {
-                self.output.push_str("int ");
-                self.output.push_str(name);
-                self.output.push_str(" = ");
-                self.emit_expr(value);
-                self.output.push_str(";\n");
+        // Only blocks that are jumped to, rather than fallen into, need a label
+        let mut targets = vec![false; func.blocks.len()];
+        for (i, block) in func.blocks.iter().enumerate() {
+            match block.terminator {
+                Terminator::Jump(target) if target.0 != i + 1 => targets[target.0] = true,
+                Terminator::Branch { if_true, if_false, .. } => {
+                    targets[if_true.0] = true;
+                    targets[if_false.0] |= if_false.0 != i + 1;
+                }
+                _ => {}
             }
+        }
 
-            Stmt::Return(expr) => {
-                self.output.push_str("return ");
-                self.emit_expr(expr);
-                self.output.push_str(";\n");
+        for (i, block) in func.blocks.iter().enumerate() {
+            if targets[i] {
+                self.line(&format!("bb{}:", i));
             }
-
-            Stmt::Assign { name, value } => {
-                self.output.push_str(name);
-                self.output.push_str(" = ");
-                self.emit_expr(value);
-                self.output.push_str(";\n");
+            for instruction in &block.instructions {
+                self.emit_instruction(instruction);
             }
+            self.emit_terminator(&block.terminator, BlockId(i + 1));
+        }
 
-            Stmt::ExprStmt(expr) => {
-                self.emit_expr(expr);
-                self.output.push_str(";\n");
-            }
+        self.line("}");
+        self.output.push('\n');
+    }
 
-            Stmt::If { condition, then_block, else_block } => {
-                self.output.push_str("if (");
-                self.emit_expr(condition);
-                self.output.push_str(") {\n");
-                self.emit_block(then_block);
-                self.output.push_str("}");
-
-                if let Some(else_block) = else_block {
-                    self.output.push_str(" else {\n");
-                    self.emit_block(else_block);
-                    self.output.push_str("}");
+    fn emit_instruction(&mut self, instruction: &Instruction) {
+        let dest = instruction.dest().0;
+        let value = match instruction {
+            Instruction::Const { value: Const::Int(v), .. } => {
+                if *v == i64::MIN {
+                    // `-9223372036854775808` is not a valid C literal
+                    "nx_int(INT64_MIN)".to_string()
+                } else {
+                    format!("nx_int(INT64_C({}))", v)
                 }
-
-                self.output.push_str("\n");
             }
-
-            Stmt::While { condition, body } => {
-                self.output.push_str("while (");
-                self.emit_expr(condition);
-                self.output.push_str(") {\n");
-                self.emit_block(body);
-                self.output.push_str("}\n");
+            Instruction::Const { value: Const::Str(s), .. } => format!("nx_str({})", c_string(s)),
+            Instruction::Const { value: Const::Void, .. } => "nx_void()".to_string(),
+            Instruction::Copy { src, .. } => format!("r{}", src.0),
+
+            Instruction::Binary { op, left, right, span, .. } => {
+                let function = match op {
+                    BinOp::Add => "nx_add",
+                    BinOp::Sub => "nx_sub",
+                    BinOp::Mul => "nx_mul",
+                    BinOp::Div => "nx_div",
+                    BinOp::Greater => "nx_greater",
+                    BinOp::Less => "nx_less",
+                    BinOp::Equal => "nx_equal",
+                };
+                format!("{}(r{}, r{}, {}, {})", function, left.0, right.0, span.line, span.column)
             }
-        }
-    }
-
 
-
-    fn emit_expr(&mut self, expr: &Expr) {
-        match expr {
-            Expr::Call { name, args } => {
-                if name == "print" {
-                    self.output.push_str("printf(");
-
-                    match args.first().unwrap() {
-                        Expr::IntLiteral(_) | Expr::VarRef(_) => {
-                            self.output.push_str("\"%d\\n\", ");
-                            self.emit_expr(&args[0]);
-                        }
-
-                        Expr::StringLiteral(_) => {
-                            self.output.push_str("\"%s\\n\", ");
-                            self.emit_expr(&args[0]);
-                        }
-
-                        _ => panic!("Unsupported print argument"),
-                    }
-
-                    self.output.push_str(")");
-                } else {
-                    self.output.push_str(name);
-                    self.output.push('(');
-                    for (i, arg) in args.iter().enumerate() {
-                        if i > 0 {
-                            self.output.push_str(", ");
-                        }
-                        self.emit_expr(arg);
+            Instruction::Call { callee, args, span, .. } => {
+                let args: Vec<String> = args.iter().map(|arg| format!("r{}", arg.0)).collect();
+
+                match callee {
+                    Callee::Builtin(Builtin::Print) => format!("nx_print({})", args[0]),
+
+                    Callee::Builtin(builtin) => format!(
+                        "nx_{}({}, {}, {}, {})",
+                        builtin.name(),
+                        args[0],
+                        args[1],
+                        span.line,
+                        span.column
+                    ),
+
+                    Callee::Function(name) => {
+                        self.line(&format!("    nx_enter({}, {});", span.line, span.column));
+                        self.line(&format!("    r{} = f_{}({});", dest, name, args.join(", ")));
+                        self.line("    nx_leave();");
+                        return;
                     }
-                    self.output.push(')');
                 }
             }
+        };
 
-            Expr::StringLiteral(value) => {
-                self.output.push('"');
-                self.output.push_str(value);
-                self.output.push('"');
-            }
-            Expr::IntLiteral(v) => {
-                self.output.push_str(&v.to_string());
-            }
-            Expr::VarRef(name) => {
-                self.output.push_str(name);
-            }
-            Expr::Binary { left, op, right } => {
-                self.output.push('(');
-                self.emit_expr(left);
-                match op {
-                    BinOp::Add => self.output.push_str(" + "),
-                    BinOp::Sub => self.output.push_str(" - "),
-                    BinOp::Mul => self.output.push_str(" * "),
-                    BinOp::Div => self.output.push_str(" / "),
-                    BinOp::Greater => self.output.push_str(" > "),
-                    BinOp::Less => self.output.push_str(" < "),
-                    BinOp::Equal => self.output.push_str(" == "),
+        self.line(&format!("    r{} = {};", dest, value));
+    }
+
+    /// End a block; `next` is the block that follows it in the file.
+    fn emit_terminator(&mut self, terminator: &Terminator, next: BlockId) {
+        match *terminator {
+            Terminator::Jump(target) if target == next => {}
+            Terminator::Jump(target) => self.line(&format!("    goto bb{};", target.0)),
 
+            Terminator::Branch { condition, if_true, if_false } => {
+                self.line(&format!("    if (nx_truthy(r{})) goto bb{};", condition.0, if_true.0));
+                if if_false != next {
+                    self.line(&format!("    goto bb{};", if_false.0));
                 }
-                self.emit_expr(right);
-                self.output.push(')');
             }
 
+            Terminator::Return(value) => self.line(&format!("    return r{};", value.0)),
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+}
+
+fn signature(func: &Function) -> String {
+    let params = if func.params.is_empty() {
+        "void".to_string()
+    } else {
+        func.params
+            .iter()
+            .map(|p| format!("nx_value r{}", p.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!("static nx_value f_{}({})", func.name, params)
+}
+
+/// `s` as a C string literal.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            // Keeps `??` from forming a trigraph
+            b'?' => out.push_str("\\?"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            // Octal escapes are at most three digits, so they cannot run
+            // into a following digit the way `\x` escapes do
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
         }
     }
-    
+    out.push('"');
+    out
 }
@@ -0,0 +1,133 @@
+use crate::span::Span;
+
+/* ======================
+    ERROR CODES
+====================== */
+
+// Every code has a long-form description in `explain.rs`; codes are
+// stable and never reused.
+
+pub const E_SYNTAX: &str = "E0001";
+pub const E_DUPLICATE_FUNCTION: &str = "E0002";
+pub const E_UNDEFINED_VARIABLE: &str = "E0003";
+pub const E_UNDEFINED_FUNCTION: &str = "E0004";
+pub const E_ARITY_MISMATCH: &str = "E0005";
+pub const E_LOOP_CONTROL_OUTSIDE_LOOP: &str = "E0006";
+pub const E_MISSING_MAIN: &str = "E0007";
+pub const E_MAIN_WITH_PARAMS: &str = "E0008";
+pub const E_UNEXPECTED_CHARACTER: &str = "E0009";
+pub const E_UNTERMINATED_STRING: &str = "E0010";
+pub const E_INTEGER_TOO_LARGE: &str = "E0011";
+pub const E_BUILTIN_REDEFINED: &str = "E0012";
+
+// Runtime errors. A missing `main` or one with parameters reuses the codes
+// above when running a bytecode file.
+pub const E_INVALID_BYTECODE: &str = "E0100";
+pub const E_TYPE_MISMATCH: &str = "E0101";
+pub const E_STACK_OVERFLOW: &str = "E0102";
+pub const E_DIVISION_BY_ZERO: &str = "E0103";
+pub const E_INTEGER_OVERFLOW: &str = "E0104";
+
+/* ======================
+    DIAGNOSTIC
+====================== */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A secondary location that helps explain a diagnostic,
+/// e.g. "first defined here".
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A fix that can be applied mechanically: replace the text covered by
+/// `span` (possibly empty, for an insertion) with `replacement`.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    /// `None` for problems with the file as a whole (e.g. no `main`).
+    pub span: Option<Span>,
+    /// Text printed under the primary span.
+    pub label: Option<String>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            label: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(
+        mut self,
+        message: impl Into<String>,
+        span: Span,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggestions.push(Suggestion {
+            message: message.into(),
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// "1 argument", "2 arguments", for arity messages.
+pub fn arguments(count: usize) -> String {
+    format!("{} argument{}", count, if count == 1 { "" } else { "s" })
+}
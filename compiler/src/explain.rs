@@ -0,0 +1,265 @@
+use crate::diagnostic::*;
+
+/// Long-form descriptions for `nexo --explain <CODE>`.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        E_SYNTAX,
+        "The parser found a token it did not expect.
+
+Erroneous code example:
+
+    fn main() {
+        let x = 1
+        print(x);
+    }
+
+Every statement ends with a `;`, and every `(` and `{` needs a matching
+`)` or `}`. The parser keeps going after a syntax error, so one mistake
+can be followed by a few more errors caused by it; fix the first one
+and compile again.",
+    ),
+    (
+        E_DUPLICATE_FUNCTION,
+        "A function was defined more than once.
+
+Erroneous code example:
+
+    fn add(a, b) { return a + b; }
+    fn add(a, b) { return b + a; }
+
+Functions live in a single global namespace and Nexo has no overloading,
+so every function needs a unique name. Rename or remove one of them.",
+    ),
+    (
+        E_UNDEFINED_VARIABLE,
+        "A variable was used or assigned before being declared with `let`.
+
+Erroneous code example:
+
+    fn main() {
+        x = 5;
+        print(y);
+    }
+
+Variables are block-scoped: a `let` inside `{ ... }` is not visible after
+the closing brace. Declare the variable with `let` in the current block
+or an enclosing one:
+
+    fn main() {
+        let x = 5;
+        print(x);
+    }",
+    ),
+    (
+        E_UNDEFINED_FUNCTION,
+        "A function was called that is not defined anywhere in the program.
+
+Erroneous code example:
+
+    fn main() {
+        foo(\"hello\");
+    }
+
+Check the spelling of the name, or define the function. The built-in
+functions are `print`, `wrapping_add`, `wrapping_sub`, `wrapping_mul`,
+`saturating_add`, `saturating_sub` and `saturating_mul`.",
+    ),
+    (
+        E_ARITY_MISMATCH,
+        "A function was called with the wrong number of arguments.
+
+Erroneous code example:
+
+    fn add(a, b) { return a + b; }
+
+    fn main() {
+        print(add(1));
+    }
+
+Pass exactly one argument per parameter in the function's definition.
+`print` takes exactly one argument; the `wrapping_*` and `saturating_*`
+built-ins take two.",
+    ),
+    (
+        E_LOOP_CONTROL_OUTSIDE_LOOP,
+        "`break` or `continue` was used outside of a `while` loop.
+
+Erroneous code example:
+
+    fn main() {
+        break;
+    }
+
+`break` and `continue` only make sense inside the body of a loop. To
+leave a function early, use `return`.",
+    ),
+    (
+        E_MISSING_MAIN,
+        "The program does not define a `main` function.
+
+Execution of a Nexo program always starts at `main`:
+
+    fn main() {
+        print(\"Hello, Nexo!\");
+    }",
+    ),
+    (
+        E_MAIN_WITH_PARAMS,
+        "The `main` function was declared with parameters.
+
+Erroneous code example:
+
+    fn main(args) {
+        print(args);
+    }
+
+Nothing passes arguments to `main`, so it must be declared as
+`fn main()`.",
+    ),
+    (
+        E_UNEXPECTED_CHARACTER,
+        "The source contains a character that is not part of Nexo's syntax.
+
+Erroneous code example:
+
+    fn main() {
+        let x = 5 @ 2;
+    }
+
+The character is skipped so that the rest of the file can still be
+checked. See docs/specs.md for the operators Nexo supports.",
+    ),
+    (
+        E_UNTERMINATED_STRING,
+        "A string literal is missing its closing `\"`.
+
+Erroneous code example:
+
+    fn main() {
+        print(\"hello);
+    }
+
+String literals cannot contain a `\"` and run until the next one, so
+everything up to the end of the file became part of the string.",
+    ),
+    (
+        E_INTEGER_TOO_LARGE,
+        "An integer literal does not fit in Nexo's 64-bit signed `int`.
+
+Erroneous code example:
+
+    fn main() {
+        print(99999999999999999999);
+    }
+
+The largest integer literal is 9223372036854775807.",
+    ),
+    (
+        E_BUILTIN_REDEFINED,
+        "A function was defined with the name of a built-in function.
+
+Erroneous code example:
+
+    fn print(x) {
+        return x;
+    }
+
+Built-in functions (`print`, `wrapping_add`, `saturating_mul`, ...)
+cannot be redefined, because calls to them would be ambiguous. Pick a
+different name.",
+    ),
+    (
+        E_INVALID_BYTECODE,
+        "The VM found bytecode it cannot execute, such as an instruction
+that pops from an empty stack or reads a local outside its call frame.
+
+Bytecode is verified before any of it runs: every jump must stay inside
+its function, every path must keep a consistent stack height and end in
+a `return`, and every call must name an existing function with the right
+number of arguments.
+
+The compiler never produces such code, so this points to a bug in the
+compiler or to a bytecode file that was modified or corrupted.
+
+A `.nxc` file is also rejected with this error when it is truncated, is
+not a bytecode file at all, or was built by a version of nexo with a
+different bytecode format. Rebuild it from source with `nexo build`.",
+    ),
+    (
+        E_TYPE_MISMATCH,
+        "An operator was applied to a value of the wrong type.
+
+Erroneous code example:
+
+    fn main() {
+        let s = \"a\";
+        print(s + 1);
+    }
+
+Arithmetic (`+ - * /`) and comparisons (`< > ==`) only accept `int`
+operands. Strings can be printed, stored and passed around, but not
+combined with operators.",
+    ),
+    (
+        E_STACK_OVERFLOW,
+        "The program nested too many function calls, almost always because
+of recursion without a base case.
+
+Erroneous code example:
+
+    fn forever(n) {
+        return forever(n + 1);
+    }
+
+Make sure every recursive function has a condition under which it
+returns without calling itself.",
+    ),
+    (
+        E_DIVISION_BY_ZERO,
+        "An integer was divided by zero.
+
+Erroneous code example:
+
+    fn main() {
+        let d = 0;
+        print(10 / d);
+    }
+
+Check the divisor before dividing:
+
+    if (d == 0) {
+        print(\"cannot divide by zero\");
+    } else {
+        print(10 / d);
+    }",
+    ),
+    (
+        E_INTEGER_OVERFLOW,
+        "The result of an arithmetic operation does not fit in a 64-bit
+signed `int` (-9223372036854775808 to 9223372036854775807).
+
+Erroneous code example:
+
+    fn main() {
+        let big = 9223372036854775807;
+        print(big + 1);
+    }
+
+Overflow is always an error in Nexo; it never wraps silently. When
+wraparound or clamping is what you want, say so explicitly with the
+built-in functions `wrapping_add`, `wrapping_sub` and `wrapping_mul`
+(which wrap around at the bounds) or `saturating_add`, `saturating_sub`
+and `saturating_mul` (which clamp to the bounds). Of these, the first
+prints -9223372036854775808 and the second 9223372036854775807:
+
+    print(wrapping_add(big, 1));
+    print(saturating_add(big, 1));",
+    ),
+];
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, text)| *text)
+}
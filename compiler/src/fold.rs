@@ -0,0 +1,122 @@
+use crate::arith;
+use crate::ast::*;
+
+/// Simplify a checked program before it reaches a backend (`-O`):
+///
+/// - binary operations on integer literals are computed, unless they would
+///   fail at run time (overflow, division by zero), which is left to the
+///   program to report
+/// - an `if` whose condition is a literal keeps only the branch it takes
+/// - a `while` whose condition is false from the start is dropped
+/// - statements after a `return`, `break` or `continue` are dropped
+pub fn fold_program(program: &mut Program) {
+    for func in &mut program.functions {
+        fold_block(&mut func.body);
+    }
+}
+
+fn fold_block(block: &mut Block) {
+    let mut statements = Vec::with_capacity(block.statements.len());
+
+    for stmt in std::mem::take(&mut block.statements) {
+        fold_stmt(stmt, &mut statements);
+
+        if statements.last().is_some_and(|s: &Stmt| {
+            matches!(s.kind, StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue)
+        }) {
+            break;
+        }
+    }
+
+    block.statements = statements;
+}
+
+/// Fold `stmt` and append what is left of it to `out`.
+fn fold_stmt(mut stmt: Stmt, out: &mut Vec<Stmt>) {
+    match &mut stmt.kind {
+        StmtKind::Let { value, .. }
+        | StmtKind::Assign { value, .. }
+        | StmtKind::ExprStmt(value)
+        | StmtKind::Return(value) => fold_expr(value),
+
+        StmtKind::If { condition, then_block, else_block } => {
+            fold_expr(condition);
+            fold_block(then_block);
+            if let Some(b) = else_block {
+                fold_block(b);
+            }
+
+            if let Some(taken) = constant_truth(condition) {
+                let branch = if taken {
+                    Some(std::mem::take(then_block))
+                } else {
+                    else_block.take()
+                };
+
+                match branch {
+                    None => {}
+                    // Without `let`s the block's scope makes no difference
+                    Some(block) if !declares_locals(&block) => out.extend(block.statements),
+                    Some(block) => {
+                        *condition = Expr { kind: ExprKind::IntLiteral(1), span: condition.span };
+                        *then_block = block;
+                        *else_block = None;
+                        out.push(stmt);
+                    }
+                }
+                return;
+            }
+        }
+
+        StmtKind::While { condition, body } => {
+            fold_expr(condition);
+            if constant_truth(condition) == Some(false) {
+                return;
+            }
+            fold_block(body);
+        }
+
+        StmtKind::Break | StmtKind::Continue => {}
+    }
+
+    out.push(stmt);
+}
+
+fn fold_expr(expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Binary { left, op, right } => {
+            fold_expr(left);
+            fold_expr(right);
+
+            let folded = match (&left.kind, &right.kind) {
+                (ExprKind::IntLiteral(a), ExprKind::IntLiteral(b)) => arith::apply(op, *a, *b).ok(),
+                _ => None,
+            };
+            if let Some(v) = folded {
+                expr.kind = ExprKind::IntLiteral(v);
+            }
+        }
+
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                fold_expr(arg);
+            }
+        }
+
+        ExprKind::IntLiteral(_) | ExprKind::VarRef(_) | ExprKind::StringLiteral(_) => {}
+    }
+}
+
+/// Whether a condition is always true or always false, if it is a literal.
+fn constant_truth(condition: &Expr) -> Option<bool> {
+    match condition.kind {
+        ExprKind::IntLiteral(v) => Some(v != 0),
+        // Only ints are truthy
+        ExprKind::StringLiteral(_) => Some(false),
+        _ => None,
+    }
+}
+
+fn declares_locals(block: &Block) -> bool {
+    block.statements.iter().any(|s| matches!(s.kind, StmtKind::Let { .. }))
+}
@@ -1,188 +1,327 @@
 use std::collections::HashMap;
+use crate::arith;
 use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::diagnostic::*;
+use crate::runtime_error::{RuntimeError, TraceFrame};
+use crate::span::Span;
+use crate::vm::{Value, MAX_FRAMES};
 
-type Env = HashMap<String, Value>;
+/// Runs a checked program straight from its AST. It is slower than the VM
+/// and kept as a reference for it: both must print the same output and
+/// report the same runtime errors.
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a FunctionDecl>,
+    /// Functions being executed, innermost last.
+    calls: Vec<Call<'a>>,
+}
 
-#[derive(Debug, Clone)]
-pub enum Value {
-    Int(i64),
-    Str(String),
-    Void,
+struct Call<'a> {
+    function: &'a str,
+    /// The call expression in the caller, `None` for `main`.
+    site: Option<Span>,
 }
 
-#[derive(Debug)]
-enum Control {
-    None,
-    Break,
-    Continue,
-    Return(Value),
+/// The variables visible in one call: a scope for each enclosing block,
+/// innermost last.
+#[derive(Default)]
+struct Env {
+    scopes: Vec<HashMap<String, Value>>,
 }
 
-pub struct Interpreter {
-    functions: HashMap<String, FunctionDecl>,
-    loop_depth: usize,
+/// Errors are boxed while they unwind so every level of recursion keeps
+/// a small frame.
+type Flow<T> = Result<T, Box<RuntimeError>>;
+
+/// What is left to run of one call's statements, innermost last.
+enum Work<'a> {
+    /// The rest of a block, whose scope is the innermost one.
+    Block(std::slice::Iter<'a, Stmt>),
+    /// A loop about to test its condition.
+    Loop { condition: &'a Expr, body: &'a Block },
 }
 
-impl Interpreter {
-    pub fn new(program: &Program) -> Self {
-        let mut functions = HashMap::new();
-        for f in &program.functions {
-            functions.insert(f.name.clone(), f.clone());
-        }
+/// What is left to do for an expression.
+enum Task<'a> {
+    Eval(&'a Expr),
+    /// Apply the operator of a binary expression, or make a call, on the
+    /// operand values computed last.
+    Finish(&'a Expr),
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let functions = program.functions
+            .iter()
+            .map(|f| (f.name.as_str(), f))
+            .collect();
 
         Interpreter {
             functions,
-            loop_depth: 0,
+            calls: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) {
-        let main = self.functions.get("main")
-            .cloned()
-            .expect("no main() function");
+    /// Run `main` to completion and return its result.
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        let main = *self.functions
+            .get("main")
+            .ok_or_else(|| RuntimeError::new(E_MISSING_MAIN, "no `main` function defined"))?;
 
-        let mut env = HashMap::new();
-        self.exec_block(&main.body, &mut env);
+        if !main.params.is_empty() {
+            return Err(RuntimeError::new(
+                E_MAIN_WITH_PARAMS,
+                "`main` must not take parameters",
+            ));
+        }
+
+        self.call(main, Vec::new(), None).map_err(|e| *e)
     }
 
-    fn exec_block(&mut self, block: &Block, env: &mut Env) -> Control {
-        for stmt in &block.statements {
-            match self.exec_stmt(stmt, env) {
-                Control::None => {}
-                c => return c,
-            }
-        }
-        Control::None
+    /// Nexo calls are the only recursion: blocks, loops and operands are
+    /// kept in work lists on the heap, so a call takes the same few Rust
+    /// frames however its body is nested.
+    fn call(
+        &mut self,
+        func: &'a FunctionDecl,
+        args: Vec<Value>,
+        site: Option<Span>,
+    ) -> Flow<Value> {
+        let mut env = Env::default();
+        env.scopes.push(func.params.iter().cloned().zip(args).collect());
+
+        self.calls.push(Call { function: &func.name, site });
+        let value = self.exec_body(&func.body, &mut env)?;
+        self.calls.pop();
+
+        Ok(value)
     }
 
-    fn exec_stmt(&mut self, stmt: &Stmt, env: &mut Env) -> Control {
-        match stmt {
-            Stmt::Let { name, value } => {
-                let v = self.eval_expr(value, env);
-                env.insert(name.clone(), v);
-                Control::None
-            }
+    /// Run a function body up to its `return`, or to its end.
+    fn exec_body(&mut self, body: &'a Block, env: &mut Env) -> Flow<Value> {
+        let mut work = vec![];
+        enter(body, &mut work, env);
 
-            Stmt::Assign { name, value } => {
-                let v = self.eval_expr(value, env);
-                let slot = env.get_mut(name)
-                    .expect("assignment to undefined variable");
-                *slot = v;
-                Control::None
-            }
+        while let Some(top) = work.last_mut() {
+            let stmt = match top {
+                Work::Block(statements) => match statements.next() {
+                    Some(stmt) => stmt,
+                    None => {
+                        work.pop();
+                        env.scopes.pop();
+                        continue;
+                    }
+                },
 
-            Stmt::ExprStmt(expr) => {
-                self.eval_expr(expr, env);
-                Control::None
-            }
+                Work::Loop { condition, body } => {
+                    let body = *body;
+                    if self.eval_expr(condition, env)?.is_truthy() {
+                        enter(body, &mut work, env);
+                    } else {
+                        work.pop();
+                    }
+                    continue;
+                }
+            };
 
-            Stmt::Return(expr) => {
-                let v = self.eval_expr(expr, env);
-                Control::Return(v)
-            }
+            match &stmt.kind {
+                StmtKind::Let { name, value } => {
+                    let v = self.eval_expr(value, env)?;
+                    env.declare(name, v);
+                }
 
-            Stmt::If { condition, then_block, else_block } => {
-                if self.eval_expr(condition, env).is_true() {
-                    self.exec_block(then_block, env)
-                } else if let Some(b) = else_block {
-                    self.exec_block(b, env)
-                } else {
-                    Control::None
+                StmtKind::Assign { name, value } => {
+                    let v = self.eval_expr(value, env)?;
+                    *env.get_mut(name) = v;
                 }
-            }
 
-            Stmt::While { condition, body } => {
-                self.loop_depth += 1;
-
-                while self.eval_expr(condition, env).is_true() {
-                    match self.exec_block(body, env) {
-                        Control::None => {}
-                        Control::Continue => continue,
-                        Control::Break => break,
-                        Control::Return(v) => {
-                            self.loop_depth -= 1;
-                            return Control::Return(v);
-                        }
-                    }
+                StmtKind::ExprStmt(expr) => {
+                    self.eval_expr(expr, env)?;
                 }
 
-                self.loop_depth -= 1;
-                Control::None
-            }
+                StmtKind::Return(expr) => return self.eval_expr(expr, env),
 
-            Stmt::Break => {
-                if self.loop_depth == 0 {
-                    panic!("break used outside loop");
+                StmtKind::If { condition, then_block, else_block } => {
+                    if self.eval_expr(condition, env)?.is_truthy() {
+                        enter(then_block, &mut work, env);
+                    } else if let Some(b) = else_block {
+                        enter(b, &mut work, env);
+                    }
                 }
-                Control::Break
-            }
 
-            Stmt::Continue => {
-                if self.loop_depth == 0 {
-                    panic!("continue used outside loop");
+                StmtKind::While { condition, body } => work.push(Work::Loop { condition, body }),
+
+                // The analyzer only allows these inside loops
+                StmtKind::Break | StmtKind::Continue => {
+                    while let Some(Work::Block(_)) = work.last() {
+                        work.pop();
+                        env.scopes.pop();
+                    }
+                    if matches!(stmt.kind, StmtKind::Break) {
+                        work.pop();
+                    }
                 }
-                Control::Continue
             }
         }
+
+        Ok(Value::Void)
     }
 
-    fn eval_expr(&mut self, expr: &Expr, env: &mut Env) -> Value {
-        match expr {
-            Expr::IntLiteral(i) => Value::Int(*i),
-            Expr::StringLiteral(s) => Value::Str(s.clone()),
-
-            Expr::VarRef(name) => env.get(name)
-                .unwrap_or_else(|| panic!("undefined variable {}", name))
-                .clone(),
-
-            Expr::Binary { left, op, right } => {
-                let l = self.eval_expr(left, env);
-                let r = self.eval_expr(right, env);
-
-                match (l, r, op) {
-                    (Value::Int(a), Value::Int(b), BinOp::Add) => Value::Int(a + b),
-                    (Value::Int(a), Value::Int(b), BinOp::Sub) => Value::Int(a - b),
-                    (Value::Int(a), Value::Int(b), BinOp::Mul) => Value::Int(a * b),
-                    (Value::Int(a), Value::Int(b), BinOp::Div) => Value::Int(a / b),
-                    (Value::Int(a), Value::Int(b), BinOp::Greater) => Value::Int((a > b) as i64),
-                    (Value::Int(a), Value::Int(b), BinOp::Less) => Value::Int((a < b) as i64),
-                    (Value::Int(a), Value::Int(b), BinOp::Equal) => Value::Int((a == b) as i64),
-                    _ => panic!("invalid binary operation"),
-                }
-            }
+    fn eval_expr(&mut self, expr: &'a Expr, env: &mut Env) -> Flow<Value> {
+        let mut tasks = vec![Task::Eval(expr)];
+        let mut values = vec![];
 
-            Expr::Call { name, args } => {
-                if name == "print" {
-                    let v = self.eval_expr(&args[0], env);
-                    match v {
-                        Value::Int(i) => println!("{}", i),
-                        Value::Str(s) => println!("{}", s),
-                        _ => {}
-                    }
-                    Value::Void
-                } else {
-                    let func = self.functions.get(name)
-                        .cloned()
-                        .unwrap_or_else(|| panic!("undefined function {}", name));
-
-                    let mut local: HashMap<String, Value> = HashMap::new();
-                    for (p, a) in func.params.iter().zip(args.iter()) {
-                        let v = self.eval_expr(a, env);
-                        local.insert(p.clone(), v);
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eval(expr) => match &expr.kind {
+                    ExprKind::IntLiteral(i) => values.push(Value::Int(*i)),
+                    ExprKind::StringLiteral(s) => values.push(Value::String(s.as_str().into())),
+
+                    ExprKind::VarRef(name) => values.push(env.get_mut(name).clone()),
+
+                    // Operands are evaluated left to right
+                    ExprKind::Binary { left, right, .. } => {
+                        tasks.push(Task::Finish(expr));
+                        tasks.push(Task::Eval(right));
+                        tasks.push(Task::Eval(left));
                     }
 
-                    match self.exec_block(&func.body, &mut local) {
-                        Control::Return(v) => v,
-                        _ => Value::Void,
+                    ExprKind::Call { args, .. } => {
+                        tasks.push(Task::Finish(expr));
+                        tasks.extend(args.iter().rev().map(Task::Eval));
                     }
+                },
+
+                Task::Finish(expr) => {
+                    let value = match &expr.kind {
+                        ExprKind::Binary { op, .. } => {
+                            let r = values.pop().expect("operands are evaluated first");
+                            let l = values.pop().expect("operands are evaluated first");
+                            self.apply(op, l, r, expr.span)?
+                        }
+
+                        ExprKind::Call { name, args } => {
+                            let args = values.split_off(values.len() - args.len());
+                            self.eval_call(name, args, expr.span)?
+                        }
+
+                        _ => unreachable!("only operators and calls are finished"),
+                    };
+                    values.push(value);
                 }
             }
         }
+
+        Ok(values.pop().expect("an expression has a value"))
+    }
+
+    fn apply(&self, op: &BinOp, l: Value, r: Value, span: Span) -> Flow<Value> {
+        let (a, b) = self.ints(l, r, op.symbol(), span)?;
+        let v = arith::apply(op, a, b).map_err(|e| self.locate(e, span))?;
+        Ok(Value::Int(v))
+    }
+
+    fn eval_call(&mut self, name: &str, values: Vec<Value>, span: Span) -> Flow<Value> {
+        if let Some(builtin) = Builtin::lookup(name) {
+            return self.call_builtin(builtin, values, span);
+        }
+
+        let func = *self.functions
+            .get(name)
+            .expect("calls are checked by the analyzer");
+
+        if self.calls.len() >= MAX_FRAMES {
+            return Err(self.stack_overflow(span).into());
+        }
+
+        self.call(func, values, Some(span))
+    }
+
+    fn call_builtin(
+        &self,
+        builtin: Builtin,
+        mut values: Vec<Value>,
+        span: Span,
+    ) -> Flow<Value> {
+        if builtin == Builtin::Print {
+            match &values[0] {
+                Value::Int(i) => println!("{}", i),
+                Value::String(s) => println!("{}", s),
+                Value::Void => println!(),
+            }
+            return Ok(Value::Void);
+        }
+
+        let b = values.pop().expect("builtin arity is checked");
+        let a = values.pop().expect("builtin arity is checked");
+        let (a, b) = self.ints(a, b, builtin.name(), span)?;
+        Ok(Value::Int(builtin.apply_int(a, b)))
+    }
+
+    fn stack_overflow(&self, span: Span) -> RuntimeError {
+        let error = RuntimeError::new(
+            E_STACK_OVERFLOW,
+            &format!("stack overflow: more than {} nested calls", MAX_FRAMES),
+        );
+        self.locate(error, span)
+    }
+
+    /// The operands of `op`, which must both be ints.
+    fn ints(&self, a: Value, b: Value, op: &str, span: Span) -> Flow<(i64, i64)> {
+        match (a, b) {
+            (Value::Int(a), Value::Int(b)) => Ok((a, b)),
+            (a, b) => {
+                let error = RuntimeError::new(
+                    E_TYPE_MISMATCH,
+                    &format!(
+                        "unsupported operand types for `{}`: {} and {}",
+                        op,
+                        a.type_name(),
+                        b.type_name()
+                    ),
+                );
+                Err(self.locate(error, span).into())
+            }
+        }
+    }
+
+    /// Attach the failing expression and the current call stack to `error`.
+    fn locate(&self, mut error: RuntimeError, span: Span) -> RuntimeError {
+        error.span = Some(span);
+
+        let mut span = Some(span);
+        for call in self.calls.iter().rev() {
+            error.trace.push(TraceFrame {
+                function: call.function.to_string(),
+                span,
+            });
+            span = call.site;
+        }
+
+        error
     }
 }
 
-impl Value {
-    fn is_true(&self) -> bool {
-        matches!(self, Value::Int(i) if *i != 0)
+/// Start running `block` in a new scope.
+fn enter<'a>(block: &'a Block, work: &mut Vec<Work<'a>>, env: &mut Env) {
+    env.scopes.push(HashMap::new());
+    work.push(Work::Block(block.statements.iter()));
+}
+
+impl Env {
+    fn declare(&mut self, name: &str, value: Value) {
+        self.scopes
+            .last_mut()
+            .expect("declarations are inside a block")
+            .insert(name.to_string(), value);
+    }
+
+    /// The innermost variable called `name`.
+    fn get_mut(&mut self, name: &str) -> &mut Value {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .expect("variables are checked by the analyzer")
     }
 }
@@ -0,0 +1,267 @@
+use crate::ast::{self, Expr, ExprKind, FunctionDecl, Stmt, StmtKind};
+use crate::builtins::Builtin;
+
+use super::*;
+
+/// Translate a checked program to IR.
+pub fn lower(program: &ast::Program) -> Program {
+    Program {
+        functions: program.functions.iter().map(lower_function).collect(),
+    }
+}
+
+fn lower_function(func: &FunctionDecl) -> Function {
+    let mut lowerer = Lowerer {
+        blocks: vec![Pending::default()],
+        current: BlockId(0),
+        registers: 0,
+        scopes: Vec::new(),
+        loops: Vec::new(),
+    };
+
+    let params: Vec<Reg> = func.params.iter().map(|_| lowerer.fresh()).collect();
+    lowerer.scopes.push(func.params.iter().cloned().zip(params.iter().copied()).collect());
+
+    lowerer.block(&func.body);
+    // Falling off the end returns void
+    let void = lowerer.fresh();
+    lowerer.push(Instruction::Const { dest: void, value: Const::Void });
+    lowerer.terminate(Terminator::Return(void));
+
+    let mut function = Function {
+        name: func.name.clone(),
+        span: func.span,
+        params,
+        types: vec![Type::Any; lowerer.registers],
+        blocks: lowerer.finish(),
+    };
+    function.infer_types();
+    function
+}
+
+/// A block being built, which has no terminator until control leaves it.
+#[derive(Default)]
+struct Pending {
+    instructions: Vec<Instruction>,
+    terminator: Option<Terminator>,
+}
+
+struct Lowerer {
+    blocks: Vec<Pending>,
+    /// Where instructions are added.
+    current: BlockId,
+    registers: usize,
+    /// Registers of the variables in scope, innermost scope last.
+    scopes: Vec<Vec<(String, Reg)>>,
+    /// `continue` and `break` targets of the enclosing loops.
+    loops: Vec<(BlockId, BlockId)>,
+}
+
+impl Lowerer {
+    fn block(&mut self, block: &ast::Block) {
+        self.scopes.push(Vec::new());
+        for stmt in &block.statements {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, value } => {
+                // The value is computed before the new variable exists
+                let var = self.compute(value, None);
+                self.scopes
+                    .last_mut()
+                    .expect("statements are inside a block")
+                    .push((name.clone(), var));
+            }
+
+            StmtKind::Assign { name, value } => {
+                let var = self.resolve(name);
+                self.compute(value, Some(var));
+            }
+
+            StmtKind::ExprStmt(expr) => {
+                self.expr(expr);
+            }
+
+            StmtKind::Return(expr) => {
+                let value = self.expr(expr);
+                self.terminate(Terminator::Return(value));
+            }
+
+            StmtKind::If { condition, then_block, else_block } => {
+                let condition = self.expr(condition);
+                let then_id = self.new_block();
+                let else_id = else_block.as_ref().map(|_| self.new_block());
+                let end = self.new_block();
+                self.terminate(Terminator::Branch {
+                    condition,
+                    if_true: then_id,
+                    if_false: else_id.unwrap_or(end),
+                });
+
+                self.current = then_id;
+                self.block(then_block);
+                self.terminate(Terminator::Jump(end));
+
+                if let (Some(else_block), Some(else_id)) = (else_block, else_id) {
+                    self.current = else_id;
+                    self.block(else_block);
+                    self.terminate(Terminator::Jump(end));
+                }
+                self.current = end;
+            }
+
+            StmtKind::While { condition, body } => {
+                let (header, body_id, end) = (self.new_block(), self.new_block(), self.new_block());
+                self.terminate(Terminator::Jump(header));
+
+                self.current = header;
+                let condition = self.expr(condition);
+                self.terminate(Terminator::Branch { condition, if_true: body_id, if_false: end });
+
+                self.current = body_id;
+                self.loops.push((header, end));
+                self.block(body);
+                self.loops.pop();
+                self.terminate(Terminator::Jump(header));
+
+                self.current = end;
+            }
+
+            StmtKind::Break => {
+                let (_, end) = *self.loops.last().expect("`break` is checked to be in a loop");
+                self.terminate(Terminator::Jump(end));
+            }
+
+            StmtKind::Continue => {
+                let (header, _) = *self.loops.last().expect("`continue` is checked to be in a loop");
+                self.terminate(Terminator::Jump(header));
+            }
+        }
+    }
+
+    /// Register holding the value of `expr`. Variables are read in place,
+    /// which is safe because no expression can assign to one.
+    fn expr(&mut self, expr: &Expr) -> Reg {
+        match &expr.kind {
+            ExprKind::VarRef(name) => self.resolve(name),
+            _ => self.compute(expr, None),
+        }
+    }
+
+    /// Compute `expr` into `dest`, or into a new register numbered after
+    /// those of its operands, and return the register written.
+    fn compute(&mut self, expr: &Expr, dest: Option<Reg>) -> Reg {
+        // Everything but the destination
+        let make: Box<dyn FnOnce(Reg) -> Instruction> = match &expr.kind {
+            ExprKind::IntLiteral(v) => {
+                let value = Const::Int(*v);
+                Box::new(move |dest| Instruction::Const { dest, value })
+            }
+            ExprKind::StringLiteral(s) => {
+                let value = Const::Str(s.clone());
+                Box::new(move |dest| Instruction::Const { dest, value })
+            }
+            ExprKind::VarRef(name) => {
+                let src = self.resolve(name);
+                Box::new(move |dest| Instruction::Copy { dest, src })
+            }
+
+            ExprKind::Binary { left, op, right } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                let (op, span) = (op.clone(), expr.span);
+                Box::new(move |dest| Instruction::Binary { dest, op, left, right, span })
+            }
+
+            ExprKind::Call { name, args } => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                let callee = match Builtin::lookup(name) {
+                    Some(builtin) => Callee::Builtin(builtin),
+                    None => Callee::Function(name.clone()),
+                };
+                let span = expr.span;
+                Box::new(move |dest| Instruction::Call { dest, callee, args, span })
+            }
+        };
+
+        let dest = dest.unwrap_or_else(|| self.fresh());
+        self.push(make(dest));
+        dest
+    }
+
+    fn fresh(&mut self) -> Reg {
+        self.registers += 1;
+        Reg(self.registers - 1)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Pending::default());
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.blocks[self.current.0].instructions.push(instruction);
+    }
+
+    /// End the current block. Code after a `return`, `break` or `continue`
+    /// goes into a new block that nothing jumps to.
+    fn terminate(&mut self, terminator: Terminator) {
+        self.blocks[self.current.0].terminator = Some(terminator);
+        self.current = self.new_block();
+    }
+
+    /// Register of the innermost variable called `name`.
+    fn resolve(&self, name: &str) -> Reg {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(var, _)| var == name)
+            .map(|&(_, reg)| reg)
+            .expect("variables are checked by the analyzer")
+    }
+
+    /// The reachable blocks, numbered in the order they were created.
+    fn finish(self) -> Vec<Block> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut worklist = vec![BlockId(0)];
+        while let Some(id) = worklist.pop() {
+            if reachable[id.0] {
+                continue;
+            }
+            reachable[id.0] = true;
+            if let Some(terminator) = &self.blocks[id.0].terminator {
+                worklist.extend(terminator.successors());
+            }
+        }
+
+        let mut new_id = Vec::with_capacity(self.blocks.len());
+        let mut kept = 0;
+        for &r in &reachable {
+            new_id.push(BlockId(kept));
+            kept += r as usize;
+        }
+
+        let renumber = |id: BlockId| new_id[id.0];
+        self.blocks
+            .into_iter()
+            .zip(reachable)
+            .filter(|(_, reachable)| *reachable)
+            .map(|(pending, _)| {
+                let terminator = match pending.terminator.expect("reachable blocks are terminated") {
+                    Terminator::Jump(target) => Terminator::Jump(renumber(target)),
+                    Terminator::Branch { condition, if_true, if_false } => Terminator::Branch {
+                        condition,
+                        if_true: renumber(if_true),
+                        if_false: renumber(if_false),
+                    },
+                    Terminator::Return(value) => Terminator::Return(value),
+                };
+                Block { phis: Vec::new(), instructions: pending.instructions, terminator }
+            })
+            .collect()
+    }
+}
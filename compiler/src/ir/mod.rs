@@ -0,0 +1,398 @@
+mod lower;
+mod optimize;
+mod ssa;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::ast::BinOp;
+use crate::builtins::Builtin;
+use crate::span::Span;
+
+pub use lower::lower;
+pub use optimize::optimize;
+
+/// A checked program as a control-flow graph of three-address code, which
+/// the bytecode and the native backends are generated from.
+///
+/// Every value lives in a virtual register. A `let` gets a register of its
+/// own and assignments write to it again, so a register can have several
+/// definitions. The optimizer works on an SSA form of each function, where
+/// every register has one and values merge in phis, and turns it back into
+/// copies afterwards; the backends never see a phi.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    /// The declaration, for code that belongs to no expression.
+    pub span: Span,
+    /// Registers holding the arguments, in order.
+    pub params: Vec<Reg>,
+    /// What each register can hold, indexed by register number.
+    pub types: Vec<Type>,
+    /// Execution starts at the first block. Every block is reachable.
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// Empty except in SSA form.
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// A register taking the value of one of `sources`, depending on which
+/// predecessor control came from.
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub dest: Reg,
+    pub sources: Vec<(BlockId, Reg)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    Const { dest: Reg, value: Const },
+    Copy { dest: Reg, src: Reg },
+    /// Fails like the VM does on overflow, division by zero or operands
+    /// that are not ints, reporting `span`.
+    Binary { dest: Reg, op: BinOp, left: Reg, right: Reg, span: Span },
+    /// A builtin or a function of the program. `span` is reported for
+    /// type errors in builtins and for calls nested too deep.
+    Call { dest: Reg, callee: Callee, args: Vec<Reg>, span: Span },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i64),
+    Str(String),
+    Void,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Builtin(Builtin),
+    Function(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// To `if_true` if `condition` is a nonzero int, `if_false` otherwise.
+    Branch { condition: Reg, if_true: BlockId, if_false: BlockId },
+    Return(Reg),
+}
+
+/// What a register can hold, as far as the IR can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Str,
+    Void,
+    /// Values of more than one type, or of one the IR does not know (what
+    /// a function returns, its arguments).
+    Any,
+}
+
+impl Instruction {
+    pub fn dest(&self) -> Reg {
+        match self {
+            Instruction::Const { dest, .. }
+            | Instruction::Copy { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Call { dest, .. } => *dest,
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> &mut Reg {
+        match self {
+            Instruction::Const { dest, .. }
+            | Instruction::Copy { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Call { dest, .. } => dest,
+        }
+    }
+
+    /// Registers the instruction reads.
+    pub fn operands(&self) -> Vec<Reg> {
+        match self {
+            Instruction::Const { .. } => Vec::new(),
+            Instruction::Copy { src, .. } => vec![*src],
+            Instruction::Binary { left, right, .. } => vec![*left, *right],
+            Instruction::Call { args, .. } => args.clone(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Reg> {
+        match self {
+            Instruction::Const { .. } => Vec::new(),
+            Instruction::Copy { src, .. } => vec![src],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Call { args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { if_true, if_false, .. } => vec![if_true, if_false],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+
+    pub fn operand(&self) -> Option<Reg> {
+        match *self {
+            Terminator::Jump(_) => None,
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Return(value) => Some(value),
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Reg> {
+        match self {
+            Terminator::Jump(_) => None,
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Return(value) => Some(value),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { if_true, if_false, .. } => vec![if_true, if_false],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+}
+
+impl Function {
+    pub fn register_count(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Predecessors of each block, in block order.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for successor in block.terminator.successors() {
+                if !predecessors[successor.0].contains(&BlockId(i)) {
+                    predecessors[successor.0].push(BlockId(i));
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// Registers whose value on entry to each block may still be read.
+    /// A phi reads its sources at the end of their predecessors.
+    pub fn live_in(&self) -> Vec<HashSet<Reg>> {
+        let mut live_in: Vec<HashSet<Reg>> = vec![HashSet::new(); self.blocks.len()];
+
+        // Backward dataflow, repeated until nothing changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, block) in self.blocks.iter().enumerate().rev() {
+                let mut live = HashSet::new();
+                for successor in block.terminator.successors() {
+                    live.extend(live_in[successor.0].iter().copied());
+                    for phi in &self.blocks[successor.0].phis {
+                        live.extend(phi.sources.iter().filter(|(b, _)| b.0 == i).map(|&(_, r)| r));
+                    }
+                }
+                live.extend(block.terminator.operand());
+
+                for instruction in block.instructions.iter().rev() {
+                    live.remove(&instruction.dest());
+                    live.extend(instruction.operands());
+                }
+                for phi in &block.phis {
+                    live.remove(&phi.dest);
+                }
+
+                if live != live_in[i] {
+                    live_in[i] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        live_in
+    }
+
+    /// Work out `types` from the definitions of each register. `types`
+    /// must already have an entry for every register.
+    pub(super) fn infer_types(&mut self) {
+        let mut types: Vec<Option<Type>> = vec![None; self.register_count()];
+        for param in &self.params {
+            types[param.0] = Some(Type::Any);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in &self.blocks {
+                let phis = block.phis.iter().map(|phi| {
+                    let sources = phi.sources.iter().map(|&(_, r)| types[r.0]);
+                    (phi.dest, sources.fold(None, join))
+                });
+                let instructions = block.instructions.iter().map(|instruction| {
+                    let defined = match instruction {
+                        Instruction::Const { value: Const::Int(_), .. } => Some(Type::Int),
+                        Instruction::Const { value: Const::Str(_), .. } => Some(Type::Str),
+                        Instruction::Const { value: Const::Void, .. } => Some(Type::Void),
+                        Instruction::Copy { src, .. } => types[src.0],
+                        // Anything else fails before producing a value
+                        Instruction::Binary { .. } => Some(Type::Int),
+                        Instruction::Call { callee: Callee::Builtin(Builtin::Print), .. } => {
+                            Some(Type::Void)
+                        }
+                        Instruction::Call { callee: Callee::Builtin(_), .. } => Some(Type::Int),
+                        Instruction::Call { callee: Callee::Function(_), .. } => Some(Type::Any),
+                    };
+                    (instruction.dest(), defined)
+                });
+                let definitions: Vec<(Reg, Option<Type>)> = phis.chain(instructions).collect();
+
+                for (dest, defined) in definitions {
+                    let joined = join(types[dest.0], defined);
+                    if joined != types[dest.0] {
+                        types[dest.0] = joined;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // Registers only defined in code that was unreachable
+        self.types = types.into_iter().map(|t| t.unwrap_or(Type::Any)).collect();
+    }
+}
+
+/// The type of a register defined as `a` in some places and `b` in others;
+/// `None` is no definition yet.
+fn join(a: Option<Type>, b: Option<Type>) -> Option<Type> {
+    match (a, b) {
+        (None, t) | (t, None) => t,
+        (Some(a), Some(b)) if a == b => Some(a),
+        _ => Some(Type::Any),
+    }
+}
+
+/* ===========================
+   Textual form (`--emit=ir`)
+=========================== */
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+        writeln!(f, "fn {}({}) {{", self.name, params.join(", "))?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i))?;
+            for phi in &block.phis {
+                let sources: Vec<String> =
+                    phi.sources.iter().map(|(b, r)| format!("[{}: {}]", b, r)).collect();
+                writeln!(f, "    {}: {} = phi {}", phi.dest, self.types[phi.dest.0], sources.join(", "))?;
+            }
+            for instruction in &block.instructions {
+                let dest = instruction.dest();
+                write!(f, "    {}: {} = ", dest, self.types[dest.0])?;
+                match instruction {
+                    Instruction::Const { value, .. } => writeln!(f, "const {}", value)?,
+                    Instruction::Copy { src, .. } => writeln!(f, "copy {}", src)?,
+                    Instruction::Binary { op, left, right, .. } => {
+                        writeln!(f, "{} {}, {}", mnemonic(op), left, right)?
+                    }
+                    Instruction::Call { callee, args, .. } => {
+                        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                        let name = match callee {
+                            Callee::Builtin(builtin) => builtin.name(),
+                            Callee::Function(name) => name,
+                        };
+                        writeln!(f, "call {}({})", name, args.join(", "))?
+                    }
+                }
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump {}", target)?,
+                Terminator::Branch { condition, if_true, if_false } => {
+                    writeln!(f, "    branch {}, {}, {}", condition, if_true, if_false)?
+                }
+                Terminator::Return(value) => writeln!(f, "    return {}", value)?,
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+fn mnemonic(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Greater => "gt",
+        BinOp::Less => "lt",
+        BinOp::Equal => "eq",
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::Int(v) => write!(f, "{}", v),
+            Const::Str(s) => write!(f, "{:?}", s),
+            Const::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Type::Int => "int",
+            Type::Str => "string",
+            Type::Void => "void",
+            Type::Any => "any",
+        })
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::arith;
+
+use super::ssa::{self, Dominators};
+use super::*;
+
+/// Optimize every function of `program` in SSA form, repeating the passes
+/// until none of them changes anything:
+///
+/// - level 1: constant propagation (which also decides constant branches
+///   and drops the blocks they no longer reach), copy propagation and dead
+///   code elimination
+/// - level 2: also common subexpression elimination and loop-invariant
+///   code motion
+///
+/// Level 0 leaves the program alone. Operations that can fail are never
+/// removed, and are only moved or merged where the same error would be
+/// reported before any other output.
+pub fn optimize(program: &mut Program, level: u8) {
+    if level == 0 {
+        return;
+    }
+
+    for func in &mut program.functions {
+        ssa::to_ssa(func);
+        loop {
+            let mut changed = propagate(func);
+            if level >= 2 {
+                changed |= eliminate_common_subexpressions(func);
+                changed |= hoist_loop_invariants(func);
+            }
+            changed |= eliminate_dead_code(func);
+            if !changed {
+                break;
+            }
+        }
+        ssa::from_ssa(func);
+        compact_registers(func);
+    }
+}
+
+/* ===========================
+   Propagation
+=========================== */
+
+/// Fold operations on constants, replace copies (and phis that merge one
+/// value) by their source, and turn branches on constants into jumps.
+fn propagate(func: &mut Function) -> bool {
+    let mut constants: HashMap<Reg, Const> = HashMap::new();
+    let mut same: HashMap<Reg, Reg> = HashMap::new();
+    let mut changed = false;
+
+    for block in &mut func.blocks {
+        for phi in &block.phis {
+            // A phi in a loop header can read itself back
+            let mut sources = phi.sources.iter().map(|&(_, r)| r).filter(|&r| r != phi.dest);
+            if let Some(first) = sources.next()
+                && sources.all(|r| r == first)
+            {
+                same.insert(phi.dest, first);
+            }
+        }
+
+        for instruction in &mut block.instructions {
+            let folded = match instruction {
+                Instruction::Const { dest, value } => {
+                    constants.insert(*dest, value.clone());
+                    None
+                }
+                Instruction::Copy { dest, src } => {
+                    same.insert(*dest, *src);
+                    None
+                }
+                Instruction::Binary { op, left, right, .. } => {
+                    match (constants.get(left), constants.get(right)) {
+                        // Operations that fail are left to report their error
+                        (Some(Const::Int(a)), Some(Const::Int(b))) => arith::apply(op, *a, *b).ok(),
+                        _ => None,
+                    }
+                }
+                Instruction::Call { callee: Callee::Builtin(builtin), args, .. }
+                    if *builtin != Builtin::Print =>
+                {
+                    match (constants.get(&args[0]), constants.get(&args[1])) {
+                        (Some(Const::Int(a)), Some(Const::Int(b))) => Some(builtin.apply_int(*a, *b)),
+                        _ => None,
+                    }
+                }
+                Instruction::Call { .. } => None,
+            };
+
+            if let Some(value) = folded {
+                let dest = instruction.dest();
+                constants.insert(dest, Const::Int(value));
+                *instruction = Instruction::Const { dest, value: Const::Int(value) };
+                changed = true;
+            }
+        }
+
+        let decided = match block.terminator {
+            Terminator::Branch { if_true, if_false, .. } if if_true == if_false => Some(if_true),
+            Terminator::Branch { condition, if_true, if_false } => match constants.get(&condition) {
+                Some(Const::Int(0)) | Some(Const::Str(_)) | Some(Const::Void) => Some(if_false),
+                Some(Const::Int(_)) => Some(if_true),
+                None => None,
+            },
+            _ => None,
+        };
+        if let Some(target) = decided {
+            block.terminator = Terminator::Jump(target);
+            changed = true;
+        }
+    }
+
+    if !same.is_empty() {
+        substitute(func, &same);
+        remove_definitions(func, &same.keys().copied().collect());
+        changed = true;
+    }
+    if changed {
+        remove_unreachable(func);
+    }
+    changed
+}
+
+/// Read `replacement[r]` (followed to the end of any chain) wherever `r`
+/// is read.
+fn substitute(func: &mut Function, replacement: &HashMap<Reg, Reg>) {
+    let resolve = |mut reg: Reg| {
+        while let Some(&next) = replacement.get(&reg) {
+            reg = next;
+        }
+        reg
+    };
+
+    for block in &mut func.blocks {
+        for phi in &mut block.phis {
+            for (_, source) in &mut phi.sources {
+                *source = resolve(*source);
+            }
+        }
+        for instruction in &mut block.instructions {
+            for operand in instruction.operands_mut() {
+                *operand = resolve(*operand);
+            }
+        }
+        if let Some(operand) = block.terminator.operand_mut() {
+            *operand = resolve(*operand);
+        }
+    }
+}
+
+/// Delete the phis and instructions writing `dests`.
+fn remove_definitions(func: &mut Function, dests: &HashSet<Reg>) {
+    for block in &mut func.blocks {
+        block.phis.retain(|phi| !dests.contains(&phi.dest));
+        block.instructions.retain(|instruction| !dests.contains(&instruction.dest()));
+    }
+}
+
+/// Drop the blocks no longer reachable from the entry, and the phi sources
+/// from blocks that are no longer predecessors.
+fn remove_unreachable(func: &mut Function) {
+    let reachable: HashSet<BlockId> = ssa::reverse_postorder(func).into_iter().collect();
+
+    let mut new_id = Vec::with_capacity(func.blocks.len());
+    let mut kept = 0;
+    for i in 0..func.blocks.len() {
+        new_id.push(BlockId(kept));
+        kept += reachable.contains(&BlockId(i)) as usize;
+    }
+
+    let mut index = 0;
+    func.blocks.retain(|_| {
+        index += 1;
+        reachable.contains(&BlockId(index - 1))
+    });
+    for block in &mut func.blocks {
+        for target in block.terminator.successors_mut() {
+            *target = new_id[target.0];
+        }
+    }
+
+    let predecessors = func.predecessors();
+    for (i, block) in func.blocks.iter_mut().enumerate() {
+        for phi in &mut block.phis {
+            phi.sources.retain(|(b, _)| reachable.contains(b));
+            for (b, _) in &mut phi.sources {
+                *b = new_id[b.0];
+            }
+            phi.sources.retain(|(b, _)| predecessors[i].contains(b));
+        }
+    }
+}
+
+/* ===========================
+   Dead code
+=========================== */
+
+/// Whether `instruction` can be removed or executed early: it has no
+/// effect and cannot fail, given what is known about its operands.
+fn is_pure(instruction: &Instruction, types: &[Type], constants: &HashMap<Reg, Const>) -> bool {
+    let ints = |a: &Reg, b: &Reg| types[a.0] == Type::Int && types[b.0] == Type::Int;
+
+    match instruction {
+        Instruction::Const { .. } | Instruction::Copy { .. } => true,
+        Instruction::Binary { op, left, right, .. } => {
+            ints(left, right)
+                && match op {
+                    BinOp::Greater | BinOp::Less | BinOp::Equal => true,
+                    BinOp::Div => {
+                        matches!(constants.get(right), Some(Const::Int(d)) if *d != 0 && *d != -1)
+                    }
+                    BinOp::Add | BinOp::Sub | BinOp::Mul => false,
+                }
+        }
+        Instruction::Call { callee: Callee::Builtin(Builtin::Print), .. } => false,
+        Instruction::Call { callee: Callee::Builtin(_), args, .. } => ints(&args[0], &args[1]),
+        Instruction::Call { callee: Callee::Function(_), .. } => false,
+    }
+}
+
+fn constants(func: &Function) -> HashMap<Reg, Const> {
+    let mut constants = HashMap::new();
+    for instruction in func.blocks.iter().flat_map(|b| &b.instructions) {
+        if let Instruction::Const { dest, value } = instruction {
+            constants.insert(*dest, value.clone());
+        }
+    }
+    constants
+}
+
+/// Remove the phis and pure instructions whose results are never read.
+fn eliminate_dead_code(func: &mut Function) -> bool {
+    func.infer_types();
+    let constants = constants(func);
+
+    // Registers read by something that stays, found backwards from what
+    // must stay anyway
+    let mut operands: HashMap<Reg, Vec<Reg>> = HashMap::new();
+    let mut worklist = Vec::new();
+    for block in &func.blocks {
+        for phi in &block.phis {
+            operands.insert(phi.dest, phi.sources.iter().map(|&(_, r)| r).collect());
+        }
+        for instruction in &block.instructions {
+            if is_pure(instruction, &func.types, &constants) {
+                operands.insert(instruction.dest(), instruction.operands());
+            } else {
+                worklist.extend(instruction.operands());
+            }
+        }
+        worklist.extend(block.terminator.operand());
+    }
+
+    let mut used = HashSet::new();
+    while let Some(reg) = worklist.pop() {
+        if used.insert(reg) {
+            worklist.extend(operands.get(&reg).into_iter().flatten());
+        }
+    }
+
+    let dead: HashSet<Reg> = operands.into_keys().filter(|reg| !used.contains(reg)).collect();
+    remove_definitions(func, &dead);
+    !dead.is_empty()
+}
+
+/* ===========================
+   Common subexpressions
+=========================== */
+
+/// What an instruction computes, for instructions that compute the same
+/// value every time they see the same operands.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Expression {
+    Int(i64),
+    Str(String),
+    Void,
+    Operation(&'static str, Reg, Reg),
+}
+
+fn expression(instruction: &Instruction) -> Option<Expression> {
+    let (name, mut a, mut b, commutative) = match instruction {
+        Instruction::Const { value: Const::Int(v), .. } => return Some(Expression::Int(*v)),
+        Instruction::Const { value: Const::Str(s), .. } => return Some(Expression::Str(s.clone())),
+        Instruction::Const { value: Const::Void, .. } => return Some(Expression::Void),
+        Instruction::Binary { op, left, right, .. } => {
+            (op.symbol(), *left, *right, matches!(op, BinOp::Add | BinOp::Mul | BinOp::Equal))
+        }
+        Instruction::Call { callee: Callee::Builtin(builtin), args, .. }
+            if *builtin != Builtin::Print =>
+        {
+            let commutative = !matches!(builtin, Builtin::WrappingSub | Builtin::SaturatingSub);
+            (builtin.name(), args[0], args[1], commutative)
+        }
+        _ => return None,
+    };
+
+    if commutative && b < a {
+        std::mem::swap(&mut a, &mut b);
+    }
+    Some(Expression::Operation(name, a, b))
+}
+
+/// Reuse the result of an earlier instruction that computes the same
+/// thing and dominates the later one. An operation that can fail is safe
+/// to merge too: if the earlier one failed, the later one never runs.
+fn eliminate_common_subexpressions(func: &mut Function) -> bool {
+    let dominators = Dominators::new(func);
+    let mut available: HashMap<Expression, Reg> = HashMap::new();
+    let mut same = HashMap::new();
+
+    // Walk the dominator tree, forgetting a block's expressions after
+    // leaving its subtree
+    enum Step {
+        Enter(BlockId),
+        Leave(Vec<Expression>),
+    }
+    let mut stack = vec![Step::Enter(BlockId(0))];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Enter(id) => {
+                let mut added = Vec::new();
+                for instruction in &func.blocks[id.0].instructions {
+                    let Some(expression) = expression(instruction) else {
+                        continue;
+                    };
+                    match available.get(&expression) {
+                        Some(&earlier) => {
+                            same.insert(instruction.dest(), earlier);
+                        }
+                        None => {
+                            added.push(expression.clone());
+                            available.insert(expression, instruction.dest());
+                        }
+                    }
+                }
+
+                stack.push(Step::Leave(added));
+                stack.extend(dominators.children[id.0].iter().map(|&child| Step::Enter(child)));
+            }
+            Step::Leave(added) => {
+                for expression in added {
+                    available.remove(&expression);
+                }
+            }
+        }
+    }
+
+    if same.is_empty() {
+        return false;
+    }
+    substitute(func, &same);
+    remove_definitions(func, &same.keys().copied().collect());
+    true
+}
+
+/* ===========================
+   Loop-invariant code motion
+=========================== */
+
+/// Move instructions whose operands do not change inside a loop to the
+/// block that enters it. Only pure instructions move, except at the top
+/// of the loop header: an instruction there that would fail on the first
+/// iteration fails just the same right before the loop, with nothing
+/// printed in between.
+fn hoist_loop_invariants(func: &mut Function) -> bool {
+    func.infer_types();
+    let constants = constants(func);
+    let dominators = Dominators::new(func);
+    let predecessors = func.predecessors();
+    let mut changed = false;
+
+    for header in 0..func.blocks.len() {
+        let latches: Vec<BlockId> = predecessors[header]
+            .iter()
+            .copied()
+            .filter(|&p| dominators.dominates(BlockId(header), p))
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+
+        // The loop: blocks that reach a latch without passing the header
+        let mut body = HashSet::from([header]);
+        let mut worklist: Vec<usize> = latches.iter().map(|b| b.0).collect();
+        while let Some(block) = worklist.pop() {
+            if body.insert(block) {
+                worklist.extend(predecessors[block].iter().map(|p| p.0));
+            }
+        }
+
+        // Only loops entered from a single block that jumps straight in
+        let outside: Vec<BlockId> =
+            predecessors[header].iter().copied().filter(|p| !body.contains(&p.0)).collect();
+        let [preheader] = outside[..] else {
+            continue;
+        };
+        if !matches!(func.blocks[preheader.0].terminator, Terminator::Jump(_)) {
+            continue;
+        }
+
+        let mut defined_inside: HashSet<Reg> = HashSet::new();
+        for &block in &body {
+            let block = &func.blocks[block];
+            defined_inside.extend(block.phis.iter().map(|phi| phi.dest));
+            defined_inside.extend(block.instructions.iter().map(|i| i.dest()));
+        }
+
+        let mut blocks: Vec<usize> = body.into_iter().collect();
+        blocks.sort();
+        for block in blocks {
+            // Whether everything before this point in the header has been
+            // moved out or is pure
+            let mut at_top = block == header;
+            let mut i = 0;
+
+            while i < func.blocks[block].instructions.len() {
+                let instruction = &func.blocks[block].instructions[i];
+                let invariant = instruction.operands().iter().all(|r| !defined_inside.contains(r));
+                let pure = is_pure(instruction, &func.types, &constants);
+                let can_fail_only = matches!(
+                    instruction,
+                    Instruction::Binary { .. } | Instruction::Call { callee: Callee::Builtin(_), .. }
+                ) && !matches!(instruction, Instruction::Call { callee: Callee::Builtin(Builtin::Print), .. });
+
+                if invariant && (pure || (at_top && can_fail_only)) {
+                    let instruction = func.blocks[block].instructions.remove(i);
+                    defined_inside.remove(&instruction.dest());
+                    func.blocks[preheader.0].instructions.push(instruction);
+                    changed = true;
+                } else {
+                    at_top &= pure;
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+/* ===========================
+   Register numbering
+=========================== */
+
+/// Renumber the registers from 0 in order of appearance, parameters first,
+/// so none are left unused.
+fn compact_registers(func: &mut Function) {
+    let mut numbers: HashMap<Reg, Reg> = HashMap::new();
+    let mut number = |reg: &mut Reg| {
+        let next = Reg(numbers.len());
+        *reg = *numbers.entry(*reg).or_insert(next);
+    };
+
+    for param in &mut func.params {
+        number(param);
+    }
+    for block in &mut func.blocks {
+        for instruction in &mut block.instructions {
+            for operand in instruction.operands_mut() {
+                number(operand);
+            }
+            number(instruction.dest_mut());
+        }
+        if let Some(operand) = block.terminator.operand_mut() {
+            number(operand);
+        }
+    }
+
+    func.types = vec![Type::Any; numbers.len()];
+    func.infer_types();
+}
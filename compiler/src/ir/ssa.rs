@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use super::*;
+
+/// The dominator tree of a function: block `a` dominates block `b` when
+/// every path from the entry to `b` goes through `a`.
+pub(super) struct Dominators {
+    /// Immediate dominator of each block; the entry is its own.
+    idom: Vec<usize>,
+    /// Blocks each block immediately dominates.
+    pub children: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    /// Cooper, Harvey and Kennedy's iterative algorithm ("A Simple, Fast
+    /// Dominance Algorithm"), over the blocks in reverse postorder.
+    pub fn new(func: &Function) -> Dominators {
+        let count = func.blocks.len();
+        let order = reverse_postorder(func);
+        let mut position = vec![0; count];
+        for (i, &block) in order.iter().enumerate() {
+            position[block.0] = i;
+        }
+
+        let predecessors = func.predecessors();
+        let mut idom: Vec<Option<usize>> = vec![None; count];
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new = None;
+                for p in &predecessors[block.0] {
+                    if idom[p.0].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => p.0,
+                        Some(other) => intersect(&idom, &position, p.0, other),
+                    });
+                }
+                if new != idom[block.0] {
+                    idom[block.0] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        let idom: Vec<usize> = idom.into_iter().map(|d| d.expect("every block is reachable")).collect();
+        let mut children = vec![Vec::new(); count];
+        for (block, &parent) in idom.iter().enumerate().skip(1) {
+            children[parent].push(BlockId(block));
+        }
+
+        Dominators { idom, children }
+    }
+
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        let mut block = b.0;
+        loop {
+            if block == a.0 {
+                return true;
+            }
+            if block == 0 {
+                return false;
+            }
+            block = self.idom[block];
+        }
+    }
+
+    /// The blocks where the region each block dominates ends: successors
+    /// of the region it does not strictly dominate.
+    fn frontiers(&self, func: &Function) -> Vec<HashSet<usize>> {
+        let mut frontiers = vec![HashSet::new(); func.blocks.len()];
+        for (block, predecessors) in func.predecessors().iter().enumerate() {
+            if predecessors.len() < 2 {
+                continue;
+            }
+            for p in predecessors {
+                let mut runner = p.0;
+                while runner != self.idom[block] {
+                    frontiers[runner].insert(block);
+                    runner = self.idom[runner];
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+fn intersect(idom: &[Option<usize>], position: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while position[a] > position[b] {
+            a = idom[a].expect("processed blocks have a dominator");
+        }
+        while position[b] > position[a] {
+            b = idom[b].expect("processed blocks have a dominator");
+        }
+    }
+    a
+}
+
+pub(super) fn reverse_postorder(func: &Function) -> Vec<BlockId> {
+    let mut visited = vec![false; func.blocks.len()];
+    let mut postorder = Vec::with_capacity(func.blocks.len());
+    // (block, successors already pushed)
+    let mut stack = vec![(0, false)];
+    visited[0] = true;
+
+    while let Some((block, expanded)) = stack.pop() {
+        if expanded {
+            postorder.push(BlockId(block));
+            continue;
+        }
+        stack.push((block, true));
+        for successor in func.blocks[block].terminator.successors().into_iter().rev() {
+            if !visited[successor.0] {
+                visited[successor.0] = true;
+                stack.push((successor.0, false));
+            }
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+/// Put `func` in SSA form (Cytron et al.): a phi wherever different
+/// definitions of a register meet and it is still live, then a new
+/// register for every definition. Parameters keep their registers.
+pub(super) fn to_ssa(func: &mut Function) {
+    let dominators = Dominators::new(func);
+    let frontiers = dominators.frontiers(func);
+    let live_in = func.live_in();
+    let count = func.register_count();
+
+    let mut defined_in: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for param in &func.params {
+        defined_in[param.0].push(0);
+    }
+    for (i, block) in func.blocks.iter().enumerate() {
+        for instruction in &block.instructions {
+            let blocks = &mut defined_in[instruction.dest().0];
+            if blocks.last() != Some(&i) {
+                blocks.push(i);
+            }
+        }
+    }
+
+    // The register each phi merges, before renaming
+    let mut merged: Vec<Vec<Reg>> = vec![Vec::new(); func.blocks.len()];
+    for (reg, blocks) in defined_in.iter().enumerate() {
+        let mut placed = HashSet::new();
+        let mut worklist = blocks.clone();
+        while let Some(block) = worklist.pop() {
+            for &frontier in &frontiers[block] {
+                if live_in[frontier].contains(&Reg(reg)) && placed.insert(frontier) {
+                    merged[frontier].push(Reg(reg));
+                    func.blocks[frontier].phis.push(Phi { dest: Reg(reg), sources: Vec::new() });
+                    if !blocks.contains(&frontier) {
+                        worklist.push(frontier);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        current: vec![Vec::new(); count],
+        next: count,
+        undefined: None,
+    };
+    for param in &func.params {
+        renamer.current[param.0].push(*param);
+    }
+    renamer.rename(func, &dominators, &merged, BlockId(0));
+
+    if let Some(undefined) = renamer.undefined {
+        let void = Instruction::Const { dest: undefined, value: Const::Void };
+        func.blocks[0].instructions.insert(0, void);
+    }
+
+    func.types = vec![Type::Any; renamer.next];
+    func.infer_types();
+}
+
+struct Renamer {
+    /// The new registers each original register has been renamed to along
+    /// the dominator tree path being walked, innermost last.
+    current: Vec<Vec<Reg>>,
+    next: usize,
+    /// Stands for registers read on a path where they were never written,
+    /// which only dead code can do.
+    undefined: Option<Reg>,
+}
+
+impl Renamer {
+    fn fresh(&mut self) -> Reg {
+        self.next += 1;
+        Reg(self.next - 1)
+    }
+
+    fn read(&mut self, reg: Reg) -> Reg {
+        if let Some(&renamed) = self.current[reg.0].last() {
+            return renamed;
+        }
+        match self.undefined {
+            Some(undefined) => undefined,
+            None => {
+                let undefined = self.fresh();
+                self.undefined = Some(undefined);
+                undefined
+            }
+        }
+    }
+
+    fn define(&mut self, reg: Reg, renamed: &mut Vec<Reg>) -> Reg {
+        let new = self.fresh();
+        self.current[reg.0].push(new);
+        renamed.push(reg);
+        new
+    }
+
+    fn rename(&mut self, func: &mut Function, dominators: &Dominators, merged: &[Vec<Reg>], id: BlockId) {
+        let mut renamed = Vec::new();
+        let block = &mut func.blocks[id.0];
+
+        for phi in &mut block.phis {
+            phi.dest = self.define(phi.dest, &mut renamed);
+        }
+        for instruction in &mut block.instructions {
+            for operand in instruction.operands_mut() {
+                *operand = self.read(*operand);
+            }
+            let dest = instruction.dest_mut();
+            *dest = self.define(*dest, &mut renamed);
+        }
+        if let Some(operand) = block.terminator.operand_mut() {
+            *operand = self.read(*operand);
+        }
+
+        let mut successors = block.terminator.successors();
+        successors.dedup();
+        for successor in successors {
+            for (i, &reg) in merged[successor.0].iter().enumerate() {
+                let value = self.read(reg);
+                func.blocks[successor.0].phis[i].sources.push((id, value));
+            }
+        }
+
+        for &child in &dominators.children[id.0] {
+            self.rename(func, dominators, merged, child);
+        }
+
+        for reg in renamed {
+            self.current[reg.0].pop();
+        }
+    }
+}
+
+/// Replace every phi with copies: each predecessor copies its value into
+/// a new register at its end, and the phi's block copies that register
+/// into the phi's at its start. Going through a register of its own keeps
+/// this correct when a block's phis read each other's results.
+pub(super) fn from_ssa(func: &mut Function) {
+    let mut next = func.register_count();
+
+    for i in 0..func.blocks.len() {
+        let phis = std::mem::take(&mut func.blocks[i].phis);
+        let mut copies = Vec::with_capacity(phis.len());
+
+        for phi in phis {
+            let incoming = Reg(next);
+            next += 1;
+            for (predecessor, src) in phi.sources {
+                let copy = Instruction::Copy { dest: incoming, src };
+                func.blocks[predecessor.0].instructions.push(copy);
+            }
+            copies.push(Instruction::Copy { dest: phi.dest, src: incoming });
+        }
+
+        func.blocks[i].instructions.splice(0..0, copies);
+    }
+
+    func.types = vec![Type::Any; next];
+    func.infer_types();
+}
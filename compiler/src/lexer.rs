@@ -1,8 +1,15 @@
-use crate::token::Token;
+use crate::diagnostic::*;
+use crate::span::Span;
+use crate::token::{SpannedToken, Token};
 
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
+    // Byte offset, line and column of `position`, for spans
+    offset: usize,
+    line: usize,
+    column: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Lexer {
@@ -10,17 +17,54 @@ impl Lexer {
         Lexer {
             input: source.chars().collect(),
             position: 0,
+            offset: 0,
+            line: 1,
+            column: 1,
+            diagnostics: Vec::new(),
         }
     }
 
+    /// Lex the whole input. The token list always ends with `EOF`;
+    /// malformed input is reported and skipped so parsing can continue.
+    pub fn tokenize(mut self) -> (Vec<SpannedToken>, Vec<Diagnostic>) {
+        let mut tokens = Vec::new();
+        loop {
+            let t = self.next_token();
+            let done = t.token == Token::EOF;
+            tokens.push(t);
+            if done {
+                break;
+            }
+        }
+        (tokens, self.diagnostics)
+    }
+
     fn current_char(&self) -> Option<char> {
         self.input.get(self.position).copied()
     }
 
     fn advance(&mut self) {
+        if let Some(c) = self.current_char() {
+            self.offset += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.position += 1;
     }
 
+    fn span_from(&self, start: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end: self.offset,
+            line,
+            column,
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.current_char() {
             if c.is_whitespace() {
@@ -46,6 +90,8 @@ impl Lexer {
     }
 
     fn read_string(&mut self) -> String {
+        let span = self.span_from(self.offset, self.line, self.column);
+
         // Skip opening quote
         self.advance();
 
@@ -60,6 +106,13 @@ impl Lexer {
 
         let value: String = self.input[start..self.position].iter().collect();
 
+        if self.current_char().is_none() {
+            self.diagnostics.push(
+                Diagnostic::error(E_UNTERMINATED_STRING, "unterminated string literal", Some(span))
+                    .with_label("string starts here"),
+            );
+        }
+
         // Skip closing quote
         self.advance();
 
@@ -67,6 +120,7 @@ impl Lexer {
     }
 
     fn read_number(&mut self) -> i64 {
+        let (offset, line, column) = (self.offset, self.line, self.column);
         let start = self.position;
 
         while let Some(c) = self.current_char() {
@@ -78,14 +132,25 @@ impl Lexer {
         }
 
         let number: String = self.input[start..self.position].iter().collect();
-        number.parse().unwrap()
+        number.parse().unwrap_or_else(|_| {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    E_INTEGER_TOO_LARGE,
+                    "integer literal is too large",
+                    Some(self.span_from(offset, line, column)),
+                )
+                .with_label("does not fit in a 64-bit signed integer"),
+            );
+            0
+        })
     }
 
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> SpannedToken {
         self.skip_whitespace();
 
-        match self.current_char() {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let token = match self.current_char() {
             Some('(') => { self.advance(); Token::LParen }
             Some(')') => { self.advance(); Token::RParen }
             Some('{') => { self.advance(); Token::LBrace }
@@ -141,10 +206,23 @@ impl Lexer {
 
             None => Token::EOF,
 
-            Some(_) => {
+            Some(c) => {
                 self.advance();
-                self.next_token()
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        E_UNEXPECTED_CHARACTER,
+                        format!("unexpected character `{}`", c),
+                        Some(self.span_from(start, line, column)),
+                    )
+                    .with_label("not valid in Nexo source"),
+                );
+                return self.next_token();
             }
+        };
+
+        SpannedToken {
+            token,
+            span: self.span_from(start, line, column),
         }
     }
 }
@@ -1,50 +1,459 @@
+mod span;
 mod token;
 mod lexer;
 mod ast;
+mod fold;
+mod ir;
+mod arith;
+mod builtins;
 mod parser;
 mod semantic;
+mod diagnostic;
+mod render;
+mod explain;
 mod vm;
+mod codegen;
+mod native;
+mod x86;
+mod interpreter;
 mod runtime_error;
 
 
 
+use codegen::CodeGenerator;
+use diagnostic::{Diagnostic, E_INVALID_BYTECODE};
+use interpreter::Interpreter;
 use lexer::Lexer;
+use native::Backend;
 use parser::Parser;
 use semantic::SemanticAnalyzer;
-use token::Token;
-use vm::{BytecodeCompiler, VM};
+use vm::{asm, nxc, VM};
+use x86::X86Generator;
 
 
 use std::env;
 use std::fs;
+use std::path::Path;
+use std::process;
+use std::thread;
+
+const USAGE: &str = "usage: nexo [run] [OPTIONS] <file.nx|file.nxc|file.nxs>
+       nexo build [OPTIONS] <file.nx|file.nxs> [-o <output>]
+       nexo disasm [OPTIONS] <file.nx|file.nxc|file.nxs>
+       nexo --explain <CODE>
+
+options:
+    --error-format=human|json   how to print diagnostics
+    --engine=vm|tree            run on the bytecode VM (default) or by
+                                walking the syntax tree of a source file
+    --emit=bytecode|ir|c|asm|exe
+                                what `build` writes: a .nxc file, the
+                                intermediate representation every backend
+                                starts from, C source, x86-64
+                                assembly, or a native executable built with
+                                `cc`. By default this follows the `-o` file:
+                                `.nxc`, `.ir`, `.c` or `.s` for those, an
+                                executable for any other name
+    --backend=asm|c             what a native executable is built from:
+                                x86-64 assembly (the default on x86-64
+                                Linux) or C (the default elsewhere)
+    -O0|-O1|-O2                 how much to optimize: not at all (the
+                                default), simplifying constant and copied
+                                values and dropping dead code, or also
+                                merging repeated computations and moving
+                                them out of loops. `-O` is `-O2`
+";
+
+/// Stack for the thread running `--engine=tree`. The interpreter stops at
+/// `vm::MAX_FRAMES` nested calls itself, and each one takes the same few
+/// Rust frames whatever the function's body looks like: under 4 KiB in a
+/// debug build, so 40 MiB for the deepest recursion.
+const TREE_STACK_SIZE: usize = 64 << 20;
+
+/// How diagnostics are printed to stderr.
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    /// One JSON object per line, for editors and CI.
+    Json,
+}
+
+/// What executes a program.
+#[derive(Clone, Copy, PartialEq)]
+enum Engine {
+    Vm,
+    /// The tree-walking interpreter, a reference for the VM.
+    Tree,
+}
+
+/// What `build` produces.
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    Bytecode,
+    /// The IR dumped as text.
+    Ir,
+    C,
+    /// x86-64 assembly for the GNU assembler.
+    Asm,
+    /// A native executable, built from the C or assembly output.
+    Exe,
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Bytecode => "nxc",
+            Emit::Ir => "ir",
+            Emit::C => "c",
+            Emit::Asm => "s",
+            Emit::Exe => "",
+        }
+    }
+
+    /// What to build when only the output file is given.
+    fn for_output(output: &Path) -> Emit {
+        match output.extension().and_then(|ext| ext.to_str()) {
+            Some("nxc") => Emit::Bytecode,
+            Some("ir") => Emit::Ir,
+            Some("c") => Emit::C,
+            Some("s") => Emit::Asm,
+            // `foo`, `foo.exe`, `a.out`...
+            _ => Emit::Exe,
+        }
+    }
+}
+
+/// Command-line flags shared by every command.
+#[derive(Clone, Copy)]
+struct Options {
+    format: ErrorFormat,
+    /// From `-O0` (none) to `-O2`.
+    opt_level: u8,
+    engine: Engine,
+    /// `None` unless `--emit` is given.
+    emit: Option<Emit>,
+    backend: Backend,
+}
 
 fn main() {
-    let filename = &env::args().collect::<Vec<_>>()[1];
-    let source = fs::read_to_string(filename).unwrap();
+    let mut options = Options {
+        format: ErrorFormat::Human,
+        opt_level: 0,
+        engine: Engine::Vm,
+        emit: None,
+        backend: Backend::host(),
+    };
+    let mut args = Vec::new();
 
-    let mut lexer = Lexer::new(&source);
-    let mut tokens = Vec::new();
-    loop {
-        let t = lexer.next_token();
-        tokens.push(t.clone());
-        if t == Token::EOF {
-            break;
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--error-format=") {
+            Some("human") => options.format = ErrorFormat::Human,
+            Some("json") => options.format = ErrorFormat::Json,
+            Some(other) => usage_error(&format!("unknown error format `{}`", other)),
+            None => match arg.strip_prefix("--engine=") {
+                Some("vm") => options.engine = Engine::Vm,
+                Some("tree") => options.engine = Engine::Tree,
+                Some(other) => usage_error(&format!("unknown engine `{}`", other)),
+                None => match arg.strip_prefix("--emit=") {
+                    Some("bytecode") => options.emit = Some(Emit::Bytecode),
+                    Some("ir") => options.emit = Some(Emit::Ir),
+                    Some("c") => options.emit = Some(Emit::C),
+                    Some("asm") => options.emit = Some(Emit::Asm),
+                    Some("exe") => options.emit = Some(Emit::Exe),
+                    Some(other) => usage_error(&format!("unknown output kind `{}`", other)),
+                    None => match arg.strip_prefix("--backend=") {
+                        Some("asm") => options.backend = Backend::Assembly,
+                        Some("c") => options.backend = Backend::C,
+                        Some(other) => usage_error(&format!("unknown backend `{}`", other)),
+                        None => match arg.as_str() {
+                            "-O0" => options.opt_level = 0,
+                            "-O1" => options.opt_level = 1,
+                            "-O" | "-O2" => options.opt_level = 2,
+                            _ => args.push(arg),
+                        },
+                    },
+                },
+            },
         }
     }
 
-    let mut parser = Parser::new(tokens);
-    let program = parser.parse_program();
+    match args.as_slice() {
+        [flag, code] if flag == "--explain" => explain_code(code),
+        [command, rest @ ..] if command == "build" => match rest {
+            [input] => build(input, None, options),
+            [input, flag, output] | [flag, output, input] if flag == "-o" => {
+                build(input, Some(Path::new(output)), options)
+            }
+            _ => usage_error("expected a source file to build"),
+        },
+        [command, filename] if command == "run" => run_file(filename, options),
+        [command, filename] if command == "disasm" => disassemble(filename, options),
+        [filename] if !filename.starts_with('-') => run_file(filename, options),
+        _ => usage_error("expected a source file"),
+    }
+}
 
-    let mut semantic = SemanticAnalyzer::new();
-    semantic.analyze(&program);
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}\n{}", message, USAGE);
+    process::exit(2);
+}
 
-    // ---- VM PATH ----
-    let compiler = BytecodeCompiler::new();
-    let bytecode = compiler.compile(&program);
-    let mut vm = VM::new(bytecode);
-    vm.run();
+fn explain_code(code: &str) {
+    match explain::explain(code) {
+        Some(text) => println!("{}", text),
+        None => {
+            eprintln!("error: no extended information for `{}`", code);
+            process::exit(1);
+        }
+    }
+}
+
+/// A program ready to run, and the source its debug info points into.
+struct Loaded {
+    bytecode: vm::bytecode::Program,
+    source_name: String,
+    /// Text of `source_name`, if the program was compiled from it just now.
+    /// Bytecode files may be older than their source, so they get none.
+    source: Option<String>,
+}
+
+/// Load a program from source (`.nx`), a bytecode file (`.nxc`) or
+/// bytecode assembly (`.nxs`), exiting on errors.
+fn load(filename: &str, options: Options) -> Loaded {
+    let format = options.format;
+    let extension = Path::new(filename).extension().and_then(|ext| ext.to_str());
+
+    let (bytecode, source_name) = match extension {
+        Some("nxc") => {
+            let bytes = fs::read(filename).unwrap_or_else(|e| {
+                eprintln!("error: cannot read `{}`: {}", filename, e);
+                process::exit(1);
+            });
+
+            nxc::decode(&bytes).unwrap_or_else(|e| {
+                let message = format!("cannot load `{}`: {}", filename, e.message);
+                report(filename, "", &[Diagnostic::error(E_INVALID_BYTECODE, message, None)], format);
+                process::exit(1);
+            })
+        }
+
+        Some("nxs") => {
+            let text = read_source(filename);
+
+            let (bytecode, source_name) = asm::assemble(&text).unwrap_or_else(|e| {
+                report(filename, &text, &[e.into()], format);
+                process::exit(1);
+            });
+
+            // Hand-written assembly has no source of its own
+            if source_name.is_empty() {
+                (bytecode, filename.to_string())
+            } else {
+                (bytecode, source_name)
+            }
+        }
+
+        _ => {
+            let source = read_source(filename);
+            let bytecode = compile(filename, &source, options);
+
+            return Loaded {
+                bytecode,
+                source_name: filename.to_string(),
+                source: Some(source),
+            };
+        }
+    };
+
+    Loaded { bytecode, source_name, source: None }
+}
+
+fn run_file(filename: &str, options: Options) {
+    let (result, source_name, source) = match options.engine {
+        Engine::Vm => {
+            let Loaded { bytecode, source_name, source } = load(filename, options);
+            let result = VM::new(bytecode).and_then(|mut vm| vm.run()).map(drop);
+            (result, source_name, source)
+        }
+
+        Engine::Tree => {
+            if is_bytecode(filename) {
+                usage_error("the tree engine runs source files only");
+            }
+
+            let source = read_source(filename);
+            let program = analyze(filename, &source, options);
+
+            // Each Nexo call nests several Rust calls, so give recursion
+            // room to reach the call limit
+            let result = thread::scope(|scope| {
+                thread::Builder::new()
+                    .stack_size(TREE_STACK_SIZE)
+                    .spawn_scoped(scope, || Interpreter::new(&program).run().map(drop))
+                    .expect("failed to start the interpreter thread")
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            });
+            (result, filename.to_string(), Some(source))
+        }
+    };
 
-    // ---- INTERPRETER (REFERENCE) ----
-    // let mut interpreter = Interpreter::new(&program);
-    // interpreter.run();
+    if let Err(e) = result {
+        let mut diagnostic = e.to_diagnostic(&source_name);
+        if source.is_none() {
+            // Without the source the trace gives locations but no snippet
+            diagnostic.span = None;
+        }
+        report(&source_name, source.as_deref().unwrap_or(""), &[diagnostic], options.format);
+        process::exit(1);
+    }
+}
+
+/// Compile `input` to bytecode, C, assembly or a native executable and
+/// save it to `output`, by default next to the input.
+fn build(input: &str, output: Option<&Path>, options: Options) {
+    let emit = match (options.emit, output) {
+        (Some(emit), _) => emit,
+        (None, Some(output)) => Emit::for_output(output),
+        (None, None) => Emit::Bytecode,
+    };
+    let default_output = Path::new(input).with_extension(emit.extension());
+    let output = output.unwrap_or(&default_output);
+
+    let contents = match emit {
+        Emit::Bytecode => {
+            let Loaded { bytecode, source_name, .. } = load(input, options);
+            nxc::encode(&bytecode, &source_name)
+        }
+
+        Emit::Ir | Emit::C | Emit::Asm | Emit::Exe => {
+            if is_bytecode(input) {
+                usage_error("IR, C, assembly and native executables are built from source files only");
+            }
+
+            let source = read_source(input);
+            let program = lower(input, &source, options);
+
+            let backend = match emit {
+                Emit::Ir => {
+                    write_output(output, program.to_string().as_bytes());
+                    return;
+                }
+                Emit::C => Backend::C,
+                Emit::Asm => Backend::Assembly,
+                _ => options.backend,
+            };
+            let code = match backend {
+                Backend::C => CodeGenerator::new(input).generate(&program),
+                Backend::Assembly => X86Generator::new(input).generate(&program),
+            };
+
+            if emit == Emit::Exe {
+                if let Err(message) = native::build_executable(&code, backend, output) {
+                    eprintln!("error: {}", message);
+                    process::exit(1);
+                }
+                return;
+            }
+            code.into_bytes()
+        }
+    };
+
+    write_output(output, &contents);
+}
+
+fn write_output(output: &Path, contents: &[u8]) {
+    if let Err(e) = fs::write(output, contents) {
+        eprintln!("error: cannot write `{}`: {}", output.display(), e);
+        process::exit(1);
+    }
+}
+
+/// Print the bytecode of a program as assembly text.
+fn disassemble(filename: &str, options: Options) {
+    let Loaded { bytecode, source_name, .. } = load(filename, options);
+    print!("{}", asm::disassemble(&bytecode, &source_name));
+}
+
+/// Whether `filename` holds bytecode or bytecode assembly rather than source.
+fn is_bytecode(filename: &str) -> bool {
+    matches!(Path::new(filename).extension().and_then(|ext| ext.to_str()), Some("nxc" | "nxs"))
+}
+
+fn read_source(filename: &str) -> String {
+    fs::read_to_string(filename).unwrap_or_else(|e| {
+        eprintln!("error: cannot read `{}`: {}", filename, e);
+        process::exit(1);
+    })
+}
+
+/// Check and compile a source file to bytecode, exiting on errors.
+fn compile(filename: &str, source: &str, options: Options) -> vm::bytecode::Program {
+    let mut bytecode = vm::compile(&lower(filename, source, options));
+    if options.opt_level > 0 {
+        vm::optimize(&mut bytecode);
+    }
+    bytecode
+}
+
+/// Check a source file and lower it to IR, optimized at `-O1` and up.
+/// Exits on errors.
+fn lower(filename: &str, source: &str, options: Options) -> ir::Program {
+    let mut program = ir::lower(&analyze(filename, source, options));
+    ir::optimize(&mut program, options.opt_level);
+    program
+}
+
+/// Check a source file and, with `-O1` and up, simplify its syntax tree for the
+/// backends. Exits on errors.
+fn analyze(filename: &str, source: &str, options: Options) -> ast::Program {
+    let mut program = check(filename, source, options.format);
+    if options.opt_level > 0 {
+        fold::fold_program(&mut program);
+    }
+    program
+}
+
+/// Run the front end (lexer, parser, semantic analysis) and print every
+/// diagnostic it produces. Exits if any of them is an error.
+fn check(filename: &str, source: &str, format: ErrorFormat) -> ast::Program {
+    let (tokens, mut diagnostics) = Lexer::new(source).tokenize();
+
+    let program = match Parser::new(tokens).parse_program() {
+        Ok(program) => Some(program),
+        Err(errors) => {
+            diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+            None
+        }
+    };
+    diagnostics.sort_by_key(|d| d.span.map(|s| s.start));
+
+    if let Some(program) = &program {
+        diagnostics.extend(SemanticAnalyzer::new().analyze(program));
+    }
+
+    report(filename, source, &diagnostics, format);
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 && format == ErrorFormat::Human {
+        eprintln!(
+            "error: aborting due to {} previous error{}",
+            errors,
+            if errors == 1 { "" } else { "s" }
+        );
+    }
+
+    match program {
+        Some(program) if !diagnostics.iter().any(|d| d.is_error()) => program,
+        _ => process::exit(1),
+    }
+}
+
+fn report(filename: &str, source: &str, diagnostics: &[Diagnostic], format: ErrorFormat) {
+    for d in diagnostics {
+        match format {
+            ErrorFormat::Human => eprintln!("{}", render::render(d, filename, source)),
+            ErrorFormat::Json => eprintln!("{}", render::render_json(d, filename, source)),
+        }
+    }
 }
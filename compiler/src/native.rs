@@ -0,0 +1,67 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+
+/// Which generated code a native executable is built from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// C generated by `CodeGenerator`, runtime included.
+    C,
+    /// Assembly generated by `X86Generator`, which `cc` only assembles and
+    /// links against the C library.
+    Assembly,
+}
+
+impl Backend {
+    /// Assembly where `X86Generator`'s output runs (x86-64 Linux), C
+    /// everywhere else.
+    pub fn host() -> Backend {
+        if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            Backend::Assembly
+        } else {
+            Backend::C
+        }
+    }
+}
+
+/// Build a native executable at `output` from generated C or assembly
+/// with the system C compiler: `$CC` if set, otherwise `cc`.
+pub fn build_executable(source: &str, backend: Backend, output: &Path) -> Result<(), String> {
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let dir = env::temp_dir().join(format!("nexo-{}", process::id()));
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("cannot create `{}`: {}", dir.display(), e))?;
+
+    let (file, flags) = match backend {
+        Backend::C => (dir.join("program.c"), &["-std=c99", "-O2", "-pthread"][..]),
+        Backend::Assembly => (dir.join("program.s"), &[][..]),
+    };
+    let result = fs::write(&file, source)
+        .map_err(|e| format!("cannot write `{}`: {}", file.display(), e))
+        .and_then(|()| {
+            Command::new(&compiler)
+                .args(flags)
+                .arg("-o")
+                .arg(output)
+                .arg(&file)
+                .output()
+                .map_err(|e| format!("cannot run the C compiler `{}`: {}", compiler, e))
+        })
+        .and_then(|out| {
+            if out.status.success() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "the C compiler `{}` failed ({}):\n{}",
+                    compiler,
+                    out.status,
+                    String::from_utf8_lossy(&out.stderr).trim_end()
+                ))
+            }
+        });
+
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
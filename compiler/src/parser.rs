@@ -1,32 +1,163 @@
 use crate::ast::*;
-use crate::token::Token;
+use crate::diagnostic::{Diagnostic, Suggestion, E_SYNTAX};
+use crate::span::Span;
+use crate::token::{SpannedToken, Token};
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// What the parser wanted instead, shown under the offending token.
+    pub expected: String,
+    pub suggestion: Option<Box<Suggestion>>,
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        let mut d = Diagnostic::error(E_SYNTAX, e.message, Some(e.span))
+            .with_label(format!("expected {}", e.expected));
+        d.suggestions.extend(e.suggestion.map(|s| *s));
+        d
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     position: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
         Parser {
             tokens,
             position: 0,
+            errors: Vec::new(),
         }
     }
 
     fn current(&self) -> &Token {
-        &self.tokens[self.position]
+        &self.tokens[self.position].token
+    }
+
+    fn current_span(&self) -> Span {
+        self.tokens[self.position].span
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.position.saturating_sub(1)].span
     }
 
     fn advance(&mut self) {
-        self.position += 1;
+        if *self.current() != Token::EOF {
+            self.position += 1;
+        }
     }
 
-    fn expect(&mut self, token: Token) {
+    fn unexpected(&self, expected: String) -> ParseError {
+        ParseError {
+            message: format!("expected {}, found {}", expected, self.current()),
+            span: self.current_span(),
+            expected,
+            suggestion: None,
+        }
+    }
+
+    fn error<T>(&self, expected: String) -> ParseResult<T> {
+        Err(self.unexpected(expected))
+    }
+
+    /// Record a recovered error. A missing `}` is seen by every enclosing
+    /// block at the same token, so only the first report is kept.
+    fn report(&mut self, error: ParseError) {
+        if self.errors.last().is_some_and(|e| e.span == error.span) {
+            return;
+        }
+        self.errors.push(error);
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<()> {
         if *self.current() == token {
             self.advance();
+            return Ok(());
+        }
+
+        let mut error = self.unexpected(token.to_string());
+
+        // A missing `;` almost always belongs right after the previous token
+        if token == Token::Semicolon && self.position > 0 {
+            let previous = &self.tokens[self.position - 1];
+            error.suggestion = Some(Box::new(Suggestion {
+                message: format!("add `;` after {}", previous.token),
+                span: previous.span.after(),
+                replacement: ";".to_string(),
+            }));
+        }
+
+        Err(error)
+    }
+
+    fn expect_ident(&mut self, what: &str) -> ParseResult<String> {
+        if let Token::Ident(n) = self.current() {
+            let n = n.clone();
+            self.advance();
+            Ok(n)
         } else {
-            panic!("Expected {:?}, got {:?}", token, self.current());
+            self.error(what.to_string())
+        }
+    }
+
+    /// Skip ahead to a point where parsing can resume after an error:
+    /// just past a `;` or a balanced `{ ... }`, before a keyword that
+    /// starts a statement, or before a `}` / `fn` that closes the
+    /// enclosing construct.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        loop {
+            match self.current() {
+                Token::EOF => return,
+                Token::Fn => return,
+                Token::RBrace if depth == 0 => return,
+                Token::Let
+                | Token::If
+                | Token::While
+                | Token::Return
+                | Token::Break
+                | Token::Continue
+                    if depth == 0 =>
+                {
+                    return
+                }
+                Token::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                Token::LBrace => depth += 1,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn binary(left: Expr, op: BinOp, right: Expr) -> Expr {
+        let span = left.span.to(right.span);
+        Expr {
+            kind: ExprKind::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            },
+            span,
         }
     }
 
@@ -34,59 +165,74 @@ impl Parser {
     // PROGRAM
     // =======================
 
-    pub fn parse_program(&mut self) -> Program {
+    /// Parse a whole file. Errors do not stop the parse: every syntax error
+    /// in the file is reported, in source order.
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut functions = Vec::new();
 
         while *self.current() != Token::EOF {
-            functions.push(self.parse_function());
+            match self.parse_function() {
+                Ok(func) => functions.push(func),
+                Err(e) => {
+                    self.report(e);
+
+                    // Resume at the next top-level `fn`
+                    if *self.current() != Token::Fn {
+                        self.advance();
+                    }
+                    while !matches!(self.current(), Token::Fn | Token::EOF) {
+                        self.advance();
+                    }
+                }
+            }
         }
 
-        Program { functions }
+        if self.errors.is_empty() {
+            Ok(Program { functions })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    fn parse_function(&mut self) -> FunctionDecl {
-        self.expect(Token::Fn);
+    fn parse_function(&mut self) -> ParseResult<FunctionDecl> {
+        let start = self.current_span();
+        self.expect(Token::Fn)?;
 
-        let name = match self.current() {
-            Token::Ident(n) => {
-                let n = n.clone();
-                self.advance();
-                n
-            }
-            _ => panic!("Expected function name"),
-        };
+        let name = self.expect_ident("function name")?;
 
-        self.expect(Token::LParen);
+        self.expect(Token::LParen)?;
 
         let mut params = Vec::new();
         while *self.current() != Token::RParen {
-            if let Token::Ident(p) = self.current() {
-                params.push(p.clone());
+            params.push(self.expect_ident("parameter name")?);
+            if *self.current() == Token::Comma {
                 self.advance();
-                if *self.current() == Token::Comma {
-                    self.advance();
-                }
-            } else {
-                panic!("Expected parameter name");
             }
         }
 
-        self.expect(Token::RParen);
-        self.expect(Token::LBrace);
+        self.expect(Token::RParen)?;
+        let span = start.to(self.previous_span());
+        self.expect(Token::LBrace)?;
 
         let body = self.parse_block();
 
-        self.expect(Token::RBrace);
+        self.expect(Token::RBrace)?;
 
-        FunctionDecl { name, params, body }
+        Ok(FunctionDecl { name, params, body, span })
     }
 
 
     fn parse_block(&mut self) -> Block {
         let mut statements = Vec::new();
 
-        while *self.current() != Token::RBrace {
-            statements.push(self.parse_statement());
+        while !matches!(self.current(), Token::RBrace | Token::Fn | Token::EOF) {
+            match self.parse_statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize();
+                }
+            }
         }
 
         Block { statements }
@@ -97,250 +243,219 @@ impl Parser {
     // =======================
 
 
-    fn parse_statement(&mut self) -> Stmt {
-        match self.current() {
-            Token::Let => self.parse_let(),
-            Token::If => self.parse_if(),
-            Token::While => self.parse_while(),
+    fn parse_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+
+        let kind = match self.current() {
+            Token::Let => self.parse_let()?,
+            Token::If => self.parse_if()?,
+            Token::While => self.parse_while()?,
             Token::Return => {
                 self.advance();
-                let expr = self.parse_expression();
-                self.expect(Token::Semicolon);
-                Stmt::Return(expr)
+                let expr = self.parse_expression()?;
+                self.expect(Token::Semicolon)?;
+                StmtKind::Return(expr)
             }
             Token::Break => {
                 self.advance();
-                self.expect(Token::Semicolon);
-                Stmt::Break
+                self.expect(Token::Semicolon)?;
+                StmtKind::Break
             }
             Token::Continue => {
                 self.advance();
-                self.expect(Token::Semicolon);
-                Stmt::Continue
+                self.expect(Token::Semicolon)?;
+                StmtKind::Continue
             }
             Token::Ident(_) => {
                 // Lookahead for assignment
-                if matches!(self.tokens[self.position + 1], Token::Equal) {
-                    self.parse_assign()
+                if matches!(self.tokens[self.position + 1].token, Token::Equal) {
+                    self.parse_assign()?
                 } else {
-                    let expr = self.parse_expression();
-                    self.expect(Token::Semicolon);
-                    Stmt::ExprStmt(expr)
+                    let expr = self.parse_expression()?;
+                    self.expect(Token::Semicolon)?;
+                    StmtKind::ExprStmt(expr)
                 }
             }
             _ => {
-                let expr = self.parse_expression();
-                self.expect(Token::Semicolon);
-                Stmt::ExprStmt(expr)
+                let expr = self.parse_expression()?;
+                self.expect(Token::Semicolon)?;
+                StmtKind::ExprStmt(expr)
             }
 
-        }
+        };
+
+        Ok(Stmt {
+            kind,
+            span: start.to(self.previous_span()),
+        })
     }
 
-    fn parse_while(&mut self) -> Stmt {
-        self.expect(Token::While);
-        self.expect(Token::LParen);
+    fn parse_while(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::While)?;
+        self.expect(Token::LParen)?;
 
-        let condition = self.parse_expression();
+        let condition = self.parse_expression()?;
 
-        self.expect(Token::RParen);
-        self.expect(Token::LBrace);
+        self.expect(Token::RParen)?;
+        self.expect(Token::LBrace)?;
 
         let body = self.parse_block();
 
-        self.expect(Token::RBrace);
+        self.expect(Token::RBrace)?;
 
-        Stmt::While { condition, body }
+        Ok(StmtKind::While { condition, body })
     }
 
 
-    fn parse_assign(&mut self) -> Stmt {
-        let name = if let Token::Ident(n) = self.current() {
-            let n = n.clone();
-            self.advance();
-            n
-        } else {
-            panic!("Expected variable name");
-        };
+    fn parse_assign(&mut self) -> ParseResult<StmtKind> {
+        let name = self.expect_ident("variable name")?;
 
-        self.expect(Token::Equal);
+        self.expect(Token::Equal)?;
 
-        let value = self.parse_expression();
-        self.expect(Token::Semicolon);
+        let value = self.parse_expression()?;
+        self.expect(Token::Semicolon)?;
 
-        Stmt::Assign { name, value }
+        Ok(StmtKind::Assign { name, value })
     }
 
 
-    fn parse_if(&mut self) -> Stmt {
-        self.expect(Token::If);
-        self.expect(Token::LParen);
+    fn parse_if(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::If)?;
+        self.expect(Token::LParen)?;
 
-        let condition = self.parse_expression();
+        let condition = self.parse_expression()?;
 
-        self.expect(Token::RParen);
-        self.expect(Token::LBrace);
+        self.expect(Token::RParen)?;
+        self.expect(Token::LBrace)?;
 
         let then_block = self.parse_block();
 
-        self.expect(Token::RBrace);
+        self.expect(Token::RBrace)?;
 
         let else_block = if *self.current() == Token::Else {
             self.advance();
-            self.expect(Token::LBrace);
+            self.expect(Token::LBrace)?;
             let block = self.parse_block();
-            self.expect(Token::RBrace);
+            self.expect(Token::RBrace)?;
             Some(block)
         } else {
             None
         };
 
-        Stmt::If {
+        Ok(StmtKind::If {
             condition,
             then_block,
             else_block,
-        }
+        })
     }
 
 
 
-    fn parse_let(&mut self) -> Stmt {
-        self.expect(Token::Let);
+    fn parse_let(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::Let)?;
 
-        let name = if let Token::Ident(n) = self.current() {
-            let n = n.clone();
-            self.advance();
-            n
-        } else {
-            panic!("Expected variable name");
-        };
+        let name = self.expect_ident("variable name")?;
 
-        self.expect(Token::Equal);
+        self.expect(Token::Equal)?;
 
-        let value = self.parse_expression();
+        let value = self.parse_expression()?;
 
-        self.expect(Token::Semicolon);
+        self.expect(Token::Semicolon)?;
 
-        Stmt::Let { name, value }
+        Ok(StmtKind::Let { name, value })
     }
 
     // =======================
     // EXPRESSIONS (PRECEDENCE)
     // =======================
 
-    fn parse_expression(&mut self) -> Expr {
+    fn parse_expression(&mut self) -> ParseResult<Expr> {
         self.parse_comparison()
     }
 
-    fn parse_comparison(&mut self) -> Expr {
-        let mut expr = self.parse_add_sub();
+    fn parse_comparison(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_add_sub()?;
 
         loop {
             match self.current() {
                 Token::Greater => {
                     self.advance();
-                    let right = self.parse_add_sub();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Greater,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_add_sub()?;
+                    expr = Self::binary(expr, BinOp::Greater, right);
                 }
                 Token::Less => {
                     self.advance();
-                    let right = self.parse_add_sub();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Less,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_add_sub()?;
+                    expr = Self::binary(expr, BinOp::Less, right);
                 }
                 Token::EqualEqual => {
                     self.advance();
-                    let right = self.parse_add_sub();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Equal,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_add_sub()?;
+                    expr = Self::binary(expr, BinOp::Equal, right);
                 }
                 _ => break,
             }
         }
 
-        expr
+        Ok(expr)
     }
 
 
     // + -
-    fn parse_add_sub(&mut self) -> Expr {
-        let mut expr = self.parse_mul_div();
+    fn parse_add_sub(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_mul_div()?;
 
         loop {
             match self.current() {
                 Token::Plus => {
                     self.advance();
-                    let right = self.parse_mul_div();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Add,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_mul_div()?;
+                    expr = Self::binary(expr, BinOp::Add, right);
                 }
                 Token::Minus => {
                     self.advance();
-                    let right = self.parse_mul_div();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Sub,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_mul_div()?;
+                    expr = Self::binary(expr, BinOp::Sub, right);
                 }
                 _ => break,
             }
         }
 
-        expr
+        Ok(expr)
     }
 
     // * /
-    fn parse_mul_div(&mut self) -> Expr {
-        let mut expr = self.parse_primary();
+    fn parse_mul_div(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_primary()?;
 
         loop {
             match self.current() {
                 Token::Star => {
                     self.advance();
-                    let right = self.parse_primary();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Mul,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_primary()?;
+                    expr = Self::binary(expr, BinOp::Mul, right);
                 }
                 Token::Slash => {
                     self.advance();
-                    let right = self.parse_primary();
-                    expr = Expr::Binary {
-                        left: Box::new(expr),
-                        op: BinOp::Div,
-                        right: Box::new(right),
-                    };
+                    let right = self.parse_primary()?;
+                    expr = Self::binary(expr, BinOp::Div, right);
                 }
                 _ => break,
             }
         }
 
-        expr
+        Ok(expr)
     }
 
     // literals, identifiers, calls, parentheses
-    fn parse_primary(&mut self) -> Expr {
-        match self.current() {
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+
+        let kind = match self.current() {
             Token::Int(value) => {
                 let v = *value;
                 self.advance();
-                Expr::IntLiteral(v)
+                ExprKind::IntLiteral(v)
             }
 
             Token::Ident(name) => {
@@ -353,33 +468,38 @@ impl Parser {
 
                     let mut args = Vec::new();
                     while *self.current() != Token::RParen {
-                        args.push(self.parse_expression());
+                        args.push(self.parse_expression()?);
                         if *self.current() == Token::Comma {
                             self.advance();
                         }
                     }
 
-                    self.expect(Token::RParen);
-                    Expr::Call { name, args }
+                    self.expect(Token::RParen)?;
+                    ExprKind::Call { name, args }
                 } else {
-                    Expr::VarRef(name)
+                    ExprKind::VarRef(name)
                 }
             }
 
             Token::LParen => {
                 self.advance();
-                let expr = self.parse_expression();
-                self.expect(Token::RParen);
-                expr
+                let expr = self.parse_expression()?;
+                self.expect(Token::RParen)?;
+                expr.kind
             }
 
             Token::String(value) => {
                 let v = value.clone();
                 self.advance();
-                Expr::StringLiteral(v)
+                ExprKind::StringLiteral(v)
             }
 
-            _ => panic!("Unexpected token {:?}", self.current()),
-        }
+            _ => return self.error("expression".to_string()),
+        };
+
+        Ok(Expr {
+            kind,
+            span: start.to(self.previous_span()),
+        })
     }
 }
@@ -0,0 +1,209 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+
+/// Render a diagnostic the way rustc does: a header with the error code,
+/// the location, and each labelled source line with the span underlined.
+///
+/// ```text
+/// error[E0003]: undefined variable `y`
+///  --> examples/foo.nx:3:11
+///   |
+/// 3 |     print(y);
+///   |           ^ not found in this scope
+/// ```
+pub fn render(diagnostic: &Diagnostic, filename: &str, source: &str) -> String {
+    let mut out = format!(
+        "{}[{}]: {}\n",
+        diagnostic.severity.as_str(),
+        diagnostic.code,
+        diagnostic.message
+    );
+
+    let Some(primary) = diagnostic.span else {
+        out.push_str(&format!(" --> {}\n", filename));
+        for note in &diagnostic.notes {
+            out.push_str(&format!("  = note: {}\n", note));
+        }
+        for suggestion in &diagnostic.suggestions {
+            out.push_str(&format!("  = help: {}\n", suggestion.message));
+        }
+        return out;
+    };
+
+    // (span, marker, text) for every label, in source order
+    let mut labels: Vec<(Span, char, &str)> =
+        vec![(primary, '^', diagnostic.label.as_deref().unwrap_or(""))];
+    for label in &diagnostic.secondary {
+        labels.push((label.span, '-', &label.message));
+    }
+    labels.sort_by_key(|(span, _, _)| (span.line, span.column));
+
+    let lines: Vec<&str> = source.split('\n').map(|l| l.trim_end_matches('\r')).collect();
+    let width = labels
+        .iter()
+        .map(|(span, _, _)| span.line.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let pad = " ".repeat(width);
+
+    out.push_str(&format!(
+        "{}--> {}:{}:{}\n",
+        pad, filename, primary.line, primary.column
+    ));
+    out.push_str(&format!("{} |\n", pad));
+
+    let mut previous_line = None;
+    for (span, marker, text) in &labels {
+        let Some(line) = lines.get(span.line - 1) else {
+            continue;
+        };
+
+        if previous_line != Some(span.line) {
+            if previous_line.is_some_and(|p| span.line > p + 1) {
+                out.push_str("...\n");
+            }
+            out.push_str(&format!("{:>width$} | {}\n", span.line, line, width = width));
+            previous_line = Some(span.line);
+        }
+
+        out.push_str(&format!("{} | {}\n", pad, underline(line, *span, *marker, text)));
+    }
+
+    if !diagnostic.notes.is_empty() || !diagnostic.suggestions.is_empty() {
+        out.push_str(&format!("{} |\n", pad));
+    }
+    for note in &diagnostic.notes {
+        out.push_str(&format!("{} = note: {}\n", pad, note));
+    }
+    for suggestion in &diagnostic.suggestions {
+        out.push_str(&format!("{} = help: {}\n", pad, suggestion.message));
+    }
+
+    out
+}
+
+/// The marker line under `line`: indentation up to the span's column
+/// (keeping tabs so it lines up), then one marker per character of the
+/// span that falls on this line.
+fn underline(line: &str, span: Span, marker: char, text: &str) -> String {
+    let mut out = String::new();
+
+    let mut chars = line.chars();
+    for c in chars.by_ref().take(span.column - 1) {
+        out.push(if c == '\t' { '\t' } else { ' ' });
+    }
+
+    let mut remaining = span.end.saturating_sub(span.start);
+    let mut count = 0;
+    for c in chars {
+        if remaining == 0 {
+            break;
+        }
+        remaining = remaining.saturating_sub(c.len_utf8());
+        count += 1;
+    }
+
+    out.extend(std::iter::repeat_n(marker, count.max(1)));
+    if !text.is_empty() {
+        out.push(' ');
+        out.push_str(text);
+    }
+    out
+}
+
+/// Render a diagnostic as a single line of JSON, for editors and CI:
+///
+/// ```text
+/// {"file":"a.nx","severity":"error","code":"E0003","message":"...",
+///  "span":{...},"label":"...","secondary":[...],"notes":[...],
+///  "suggestions":[{"message":"...","span":{...},"replacement":"..."}]}
+/// ```
+///
+/// Spans carry byte offsets plus 1-based start/end lines and columns.
+pub fn render_json(diagnostic: &Diagnostic, filename: &str, source: &str) -> String {
+    let span = match diagnostic.span {
+        Some(span) => json_span(span, source),
+        None => "null".to_string(),
+    };
+    let label = match &diagnostic.label {
+        Some(label) => json_string(label),
+        None => "null".to_string(),
+    };
+
+    let secondary: Vec<String> = diagnostic
+        .secondary
+        .iter()
+        .map(|l| {
+            format!(
+                "{{\"span\":{},\"message\":{}}}",
+                json_span(l.span, source),
+                json_string(&l.message)
+            )
+        })
+        .collect();
+
+    let notes: Vec<String> = diagnostic.notes.iter().map(|n| json_string(n)).collect();
+
+    let suggestions: Vec<String> = diagnostic
+        .suggestions
+        .iter()
+        .map(|s| {
+            format!(
+                "{{\"message\":{},\"span\":{},\"replacement\":{}}}",
+                json_string(&s.message),
+                json_span(s.span, source),
+                json_string(&s.replacement)
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"file\":{},\"severity\":{},\"code\":{},\"message\":{},\"span\":{},\"label\":{},\"secondary\":[{}],\"notes\":[{}],\"suggestions\":[{}]}}",
+        json_string(filename),
+        json_string(diagnostic.severity.as_str()),
+        json_string(diagnostic.code),
+        json_string(&diagnostic.message),
+        span,
+        label,
+        secondary.join(","),
+        notes.join(","),
+        suggestions.join(",")
+    )
+}
+
+fn json_span(span: Span, source: &str) -> String {
+    let (line_start, column_start) = line_column(source, span.start);
+    let (line_end, column_end) = line_column(source, span.end);
+
+    format!(
+        "{{\"byte_start\":{},\"byte_end\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{}}}",
+        span.start, span.end, line_start, column_start, line_end, column_end
+    )
+}
+
+/// 1-based line and column (in characters) of a byte offset.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,14 +1,71 @@
-#[allow(dead_code)]
-#[derive(Debug)]
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+
+const MAX_REPEATED_FRAMES: usize = 3;
+
+/// One entry of a Nexo-level stack trace.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub function: String,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
 pub struct RuntimeError {
+    pub code: &'static str,
     pub message: String,
+    /// Source of the instruction that failed, when known.
+    pub span: Option<Span>,
+    /// Innermost call first.
+    pub trace: Vec<TraceFrame>,
 }
 
 
 impl RuntimeError {
-    pub fn new(msg: &str) -> Self {
+    pub fn new(code: &'static str, msg: &str) -> Self {
         RuntimeError {
+            code,
             message: msg.to_string(),
+            span: None,
+            trace: Vec::new(),
         }
     }
+
+    pub fn to_diagnostic(&self, filename: &str) -> Diagnostic {
+        let mut d = Diagnostic::error(self.code, self.message.clone(), self.span);
+
+        // Deep recursion repeats the same frame thousands of times; show a
+        // few and summarize the rest
+        let mut i = 0;
+        while i < self.trace.len() {
+            let frame = &self.trace[i];
+            let run = self.trace[i..]
+                .iter()
+                .take_while(|f| f.function == frame.function && f.span == frame.span)
+                .count();
+
+            let location = match frame.span {
+                Some(span) => format!(
+                    "at {} ({}:{}:{})",
+                    frame.function, filename, span.line, span.column
+                ),
+                None => format!("at {} ({})", frame.function, filename),
+            };
+            for _ in 0..run.min(MAX_REPEATED_FRAMES) {
+                d = d.with_note(location.clone());
+            }
+            if run > MAX_REPEATED_FRAMES {
+                let more = run - MAX_REPEATED_FRAMES;
+                d = d.with_note(format!(
+                    "[previous frame repeated {} more time{}]",
+                    more,
+                    if more == 1 { "" } else { "s" }
+                ));
+            }
+
+            i += run;
+        }
+
+        d
+    }
 }
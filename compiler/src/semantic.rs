@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::diagnostic::*;
+use crate::span::Span;
 
 pub struct SemanticAnalyzer {
     functions: HashMap<String, FunctionDecl>,
     scopes: Vec<HashMap<String, ()>>,
     in_loop: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl SemanticAnalyzer {
@@ -13,26 +17,80 @@ impl SemanticAnalyzer {
             functions: HashMap::new(),
             scopes: Vec::new(),
             in_loop: false,
+            diagnostics: Vec::new(),
         }
     }
 
-    pub fn analyze(&mut self, program: &Program) {
+    /// Check the whole program and return every problem found, in source
+    /// order. The program is only safe to run if none of them is an error.
+    pub fn analyze(&mut self, program: &Program) -> Vec<Diagnostic> {
         self.collect_functions(program);
+        self.check_main();
 
         for func in &program.functions {
             self.check_function(func);
         }
+
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        diagnostics.sort_by_key(|d| d.span.map(|s| s.start));
+        diagnostics
     }
 
     fn collect_functions(&mut self, program: &Program) {
         for func in &program.functions {
-            if self.functions.contains_key(&func.name) {
-                panic!("Semantic error: duplicate function `{}`", func.name);
+            if Builtin::lookup(&func.name).is_some() {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        E_BUILTIN_REDEFINED,
+                        format!("cannot redefine built-in function `{}`", func.name),
+                        Some(func.span),
+                    )
+                    .with_label("conflicts with a built-in function"),
+                );
+                continue;
+            }
+
+            if let Some(first) = self.functions.get(&func.name) {
+                let first = first.span;
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        E_DUPLICATE_FUNCTION,
+                        format!("duplicate function `{}`", func.name),
+                        Some(func.span),
+                    )
+                    .with_label(format!("`{}` redefined here", func.name))
+                    .with_secondary(first, format!("previous definition of `{}` here", func.name)),
+                );
+                continue;
             }
             self.functions.insert(func.name.clone(), func.clone());
         }
     }
 
+    fn check_main(&mut self) {
+        match self.functions.get("main") {
+            None => {
+                self.diagnostics.push(
+                    Diagnostic::error(E_MISSING_MAIN, "no `main` function defined", None)
+                        .with_note("execution starts at `fn main()`"),
+                );
+            }
+            Some(main) if !main.params.is_empty() => {
+                let span = main.span;
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        E_MAIN_WITH_PARAMS,
+                        "`main` must not take parameters",
+                        Some(span),
+                    )
+                    .with_label("declared with parameters here")
+                    .with_suggestion("remove the parameters", span, "fn main()"),
+                );
+            }
+            Some(_) => {}
+        }
+    }
+
     /* ======================
         SCOPE MANAGEMENT
     ====================== */
@@ -52,6 +110,10 @@ impl SemanticAnalyzer {
             .insert(name.to_string(), ());
     }
 
+    fn variables(&self) -> impl Iterator<Item = &str> {
+        self.scopes.iter().flat_map(|scope| scope.keys().map(String::as_str))
+    }
+
     fn is_var_defined(&self, name: &str) -> bool {
         for scope in self.scopes.iter().rev() {
             if scope.contains_key(name) {
@@ -93,28 +155,52 @@ impl SemanticAnalyzer {
     }
 
     fn check_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Let { name, value } => {
+        let span = stmt.span;
+
+        match &stmt.kind {
+            StmtKind::Let { name, value } => {
                 self.check_expr(value);
                 self.declare_var(name);
             }
 
-            Stmt::Assign { name, value } => {
+            StmtKind::Assign { name, value } => {
                 if !self.is_var_defined(name) {
-                    panic!("Semantic error: assigning to undefined variable `{}`", name);
+                    // The statement starts with the assigned name
+                    let name_span = Span { end: span.start + name.len(), ..span };
+
+                    let diagnostic = Diagnostic::error(
+                        E_UNDEFINED_VARIABLE,
+                        format!("assignment to undefined variable `{}`", name),
+                        Some(span),
+                    )
+                    .with_label("not declared in this scope");
+
+                    let diagnostic = match closest(name, self.variables()) {
+                        Some(similar) => diagnostic.with_suggestion(
+                            format!("a variable with a similar name exists: `{}`", similar),
+                            name_span,
+                            similar,
+                        ),
+                        None => diagnostic.with_suggestion(
+                            format!("declare `{}` with `let`", name),
+                            Span { end: span.start, ..span },
+                            "let ",
+                        ),
+                    };
+                    self.diagnostics.push(diagnostic);
                 }
                 self.check_expr(value);
             }
 
-            Stmt::Return(expr) => {
+            StmtKind::Return(expr) => {
                 self.check_expr(expr);
             }
 
-            Stmt::ExprStmt(expr) => {
+            StmtKind::ExprStmt(expr) => {
                 self.check_expr(expr);
             }
 
-            Stmt::If {
+            StmtKind::If {
                 condition,
                 then_block,
                 else_block,
@@ -126,7 +212,7 @@ impl SemanticAnalyzer {
                 }
             }
 
-            Stmt::While { condition, body } => {
+            StmtKind::While { condition, body } => {
                 self.check_expr(condition);
 
                 let old = self.in_loop;
@@ -135,9 +221,21 @@ impl SemanticAnalyzer {
                 self.in_loop = old;
             }
 
-            Stmt::Break | Stmt::Continue => {
+            StmtKind::Break | StmtKind::Continue => {
                 if !self.in_loop {
-                    panic!("break/continue used outside loop");
+                    let keyword = if matches!(stmt.kind, StmtKind::Break) {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            E_LOOP_CONTROL_OUTSIDE_LOOP,
+                            format!("`{}` outside of a loop", keyword),
+                            Some(span),
+                        )
+                        .with_label(format!("cannot `{}` outside of a loop", keyword)),
+                    );
                 }
             }
 
@@ -149,29 +247,78 @@ impl SemanticAnalyzer {
     ====================== */
 
     fn check_expr(&mut self, expr: &Expr) {
-        match expr {
-            Expr::VarRef(name) => {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExprKind::VarRef(name) => {
                 if !self.is_var_defined(name) {
-                    panic!("Semantic error: undefined variable `{}`", name);
+                    let mut diagnostic = Diagnostic::error(
+                        E_UNDEFINED_VARIABLE,
+                        format!("undefined variable `{}`", name),
+                        Some(span),
+                    )
+                    .with_label("not found in this scope");
+
+                    if let Some(similar) = closest(name, self.variables()) {
+                        diagnostic = diagnostic.with_suggestion(
+                            format!("a variable with a similar name exists: `{}`", similar),
+                            span,
+                            similar,
+                        );
+                    }
+                    self.diagnostics.push(diagnostic);
                 }
             }
 
-            Expr::Call { name, args } => {
-                if name == "print" {
-                    if args.len() != 1 {
-                        panic!("Semantic error: print expects 1 argument");
+            ExprKind::Call { name, args } => {
+                if let Some(builtin) = Builtin::lookup(name) {
+                    let arity = builtin.arity();
+                    if args.len() != arity {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                E_ARITY_MISMATCH,
+                                format!("`{}` expects {}, got {}", name, arguments(arity), args.len()),
+                                Some(span),
+                            )
+                            .with_label(format!("expected {}", arguments(arity))),
+                        );
                     }
                 } else if let Some(func) = self.functions.get(name) {
                     if args.len() != func.params.len() {
-                        panic!(
-                            "Semantic error: function `{}` expects {} args, got {}",
-                            name,
-                            func.params.len(),
-                            args.len()
-                        );
+                        let diagnostic = Diagnostic::error(
+                            E_ARITY_MISMATCH,
+                            format!(
+                                "function `{}` expects {}, got {}",
+                                name,
+                                arguments(func.params.len()),
+                                args.len()
+                            ),
+                            Some(span),
+                        )
+                        .with_label(format!("expected {}", arguments(func.params.len())))
+                        .with_secondary(func.span, format!("`{}` defined here", name));
+                        self.diagnostics.push(diagnostic);
                     }
                 } else {
-                    panic!("Semantic error: undefined function `{}`", name);
+                    let mut diagnostic = Diagnostic::error(
+                        E_UNDEFINED_FUNCTION,
+                        format!("undefined function `{}`", name),
+                        Some(span),
+                    )
+                    .with_label("not found in this program");
+
+                    let functions = self.functions.keys().map(String::as_str);
+                    let builtins = Builtin::ALL.into_iter().map(|b| b.name());
+                    if let Some(similar) = closest(name, functions.chain(builtins)) {
+                        // The call starts with the callee's name
+                        let name_span = Span { end: span.start + name.len(), ..span };
+                        diagnostic = diagnostic.with_suggestion(
+                            format!("a function with a similar name exists: `{}`", similar),
+                            name_span,
+                            similar,
+                        );
+                    }
+                    self.diagnostics.push(diagnostic);
                 }
 
                 for arg in args {
@@ -180,12 +327,42 @@ impl SemanticAnalyzer {
             }
 
 
-            Expr::Binary { left, right, .. } => {
+            ExprKind::Binary { left, right, .. } => {
                 self.check_expr(left);
                 self.check_expr(right);
             }
 
-            Expr::IntLiteral(_) | Expr::StringLiteral(_) => {}
+            ExprKind::IntLiteral(_) | ExprKind::StringLiteral(_) => {}
+        }
+    }
+}
+
+/// The candidate closest to `name` by edit distance, if it is close enough
+/// to plausibly be a typo of it.
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+
+    candidates
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min()
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
         }
     }
+
+    row[b.len()]
 }
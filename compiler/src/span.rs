@@ -0,0 +1,35 @@
+/// A region of source text.
+///
+/// `start` and `end` are byte offsets into the file (end exclusive);
+/// `line` and `column` are 1-based and point at the first character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Span covering everything from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Empty span just past the end of `self`, where text can be inserted.
+    /// The column is exact for spans on one line of ASCII text, which
+    /// covers every token except some string literals.
+    pub fn after(self) -> Span {
+        Span {
+            start: self.end,
+            end: self.end,
+            line: self.line,
+            column: self.column + (self.end - self.start),
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use std::fmt;
+
+use crate::span::Span;
+
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     // Keywords
     Fn,
@@ -41,3 +46,48 @@ pub enum Token {
     // Special
     EOF,
 }
+
+/// A token together with where it appeared in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::Fn => "fn",
+            Token::Let => "let",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::For => "for",
+            Token::Return => "return",
+            Token::While => "while",
+            Token::Break => "break",
+            Token::Continue => "continue",
+
+            Token::Ident(name) => return write!(f, "identifier `{}`", name),
+            Token::Int(v) => return write!(f, "integer `{}`", v),
+            Token::String(s) => return write!(f, "string \"{}\"", s),
+
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Equal => "=",
+            Token::Greater => ">",
+            Token::Less => "<",
+            Token::EqualEqual => "==",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+
+            Token::EOF => return write!(f, "end of file"),
+        };
+        write!(f, "`{}`", text)
+    }
+}
@@ -0,0 +1,476 @@
+//! A text form of bytecode (`.nxs`), for reading and hand-editing compiled
+//! programs. `disassemble` and `assemble` round-trip: assembling the
+//! output of `disassemble` gives back the same program.
+//!
+//! ```text
+//! .source "examples/functions.nx"
+//!
+//! fn add 2
+//!     .loc 2:5 22..35
+//!     load 0
+//!     load 1
+//!     add
+//!     return
+//! L0:
+//!     jump_if_false L1
+//! ```
+//!
+//! Each function starts with `fn NAME ARITY`. Jump targets are labels and
+//! calls name their callee. `.loc LINE:COLUMN START..END` sets the source
+//! span of the instructions that follow, and `;` starts a comment.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::builtins::Builtin;
+use crate::diagnostic::{Diagnostic, E_INVALID_BYTECODE};
+use crate::span::Span;
+
+use super::bytecode::{Function, Instruction, Program, SpanTable};
+use super::value::Value;
+
+/* ===========================
+   Disassembler
+=========================== */
+
+/// Print `program` as assembly text. `source` is the file it was built from.
+pub fn disassemble(program: &Program, source: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, ".source {}", quote(source)).unwrap();
+
+    // Every jump target gets a label, numbered in code order
+    let mut targets: Vec<usize> = program.code
+        .iter()
+        .filter_map(|instr| match *instr {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => Some(target),
+            _ => None,
+        })
+        .collect();
+    targets.sort();
+    targets.dedup();
+    let label = |ip: usize| format!("L{}", targets.binary_search(&ip).unwrap());
+
+    let mut functions: Vec<&Function> = program.functions.iter().collect();
+    functions.sort_by_key(|f| f.entry);
+    let mut functions = functions.into_iter().peekable();
+
+    let mut runs = program.spans.runs().iter().peekable();
+
+    for (ip, instr) in program.code.iter().enumerate() {
+        while let Some(f) = functions.next_if(|f| f.entry == ip) {
+            writeln!(out, "\nfn {} {}", f.name, f.arity).unwrap();
+        }
+        if targets.binary_search(&ip).is_ok() {
+            writeln!(out, "{}:", label(ip)).unwrap();
+        }
+        if let Some((_, span)) = runs.next_if(|(first, _)| *first == ip) {
+            writeln!(
+                out,
+                "    .loc {}:{} {}..{}",
+                span.line, span.column, span.start, span.end
+            )
+            .unwrap();
+        }
+
+        let text = match *instr {
+            Instruction::PushInt(v) => format!("push_int {}", v),
+            Instruction::PushConst(index) => match &program.constants[index as usize] {
+                Value::Int(v) => format!("push_int {}", v),
+                Value::String(s) => format!("push_str {}", quote(s)),
+                Value::Void => "push_void".to_string(),
+            },
+            Instruction::LoadLocal(slot) => format!("load {}", slot),
+            Instruction::StoreLocal(slot) => format!("store {}", slot),
+            Instruction::Call(function) => {
+                format!("call {}", program.functions[function as usize].name)
+            }
+            Instruction::CallBuiltin(builtin) => format!("builtin {}", builtin.name()),
+            Instruction::Jump(target) => format!("jump {}", label(target)),
+            Instruction::JumpIfFalse(target) => format!("jump_if_false {}", label(target)),
+            _ => mnemonic(instr).to_string(),
+        };
+        writeln!(out, "    {}", text).unwrap();
+    }
+
+    out
+}
+
+/// Name of an instruction that has no operand.
+fn mnemonic(instr: &Instruction) -> &'static str {
+    match instr {
+        Instruction::PushVoid => "push_void",
+        Instruction::Add => "add",
+        Instruction::Sub => "sub",
+        Instruction::Mul => "mul",
+        Instruction::Div => "div",
+        Instruction::Less => "less",
+        Instruction::Equal => "equal",
+        Instruction::Greater => "greater",
+        Instruction::Return => "return",
+        Instruction::Pop => "pop",
+        Instruction::Halt => "halt",
+        _ => unreachable!("{:?} has an operand", instr),
+    }
+}
+
+const NO_OPERAND: [Instruction; 11] = [
+    Instruction::PushVoid,
+    Instruction::Add,
+    Instruction::Sub,
+    Instruction::Mul,
+    Instruction::Div,
+    Instruction::Less,
+    Instruction::Equal,
+    Instruction::Greater,
+    Instruction::Return,
+    Instruction::Pop,
+    Instruction::Halt,
+];
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/* ===========================
+   Assembler
+=========================== */
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl From<AsmError> for Diagnostic {
+    fn from(e: AsmError) -> Self {
+        Diagnostic::error(E_INVALID_BYTECODE, e.message, e.span)
+    }
+}
+
+/// Read assembly text back into a program, returning it with the name of
+/// the source file it was built from.
+pub fn assemble(text: &str) -> Result<(Program, String), AsmError> {
+    Assembler::default().run(text)
+}
+
+#[derive(Default)]
+struct Assembler {
+    source: String,
+    functions: Vec<Function>,
+    function_index: HashMap<String, u32>,
+    constants: Vec<Value>,
+    string_constants: HashMap<String, u32>,
+    code: Vec<Instruction>,
+    spans: SpanTable,
+    span: Option<Span>,
+    labels: HashMap<String, usize>,
+    // Instructions whose operand names something defined later
+    jumps: Vec<(usize, Word)>,
+    calls: Vec<(usize, Word)>,
+}
+
+/// A word of assembly text and where it is.
+#[derive(Clone)]
+struct Word {
+    text: String,
+    span: Span,
+}
+
+impl Assembler {
+    fn run(mut self, text: &str) -> Result<(Program, String), AsmError> {
+        let mut offset = 0;
+        for (i, line) in text.split('\n').enumerate() {
+            let words = split_words(line, offset, i + 1)?;
+            offset += line.len() + 1;
+
+            if let Some((first, rest)) = words.split_first() {
+                self.line(first, rest)?;
+            }
+        }
+
+        for (ip, word) in std::mem::take(&mut self.jumps) {
+            let target = self.label(&word)?;
+            self.code[ip] = match self.code[ip] {
+                Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+                _ => Instruction::Jump(target),
+            };
+        }
+
+        for (ip, word) in std::mem::take(&mut self.calls) {
+            let index = *self.function_index
+                .get(&word.text)
+                .ok_or_else(|| error(&word, format!("undefined function `{}`", word.text)))?;
+            self.code[ip] = Instruction::Call(index);
+        }
+
+        if let Some(f) = self.functions.iter().find(|f| f.entry >= self.code.len()) {
+            return Err(AsmError {
+                message: format!("function `{}` has no instructions", f.name),
+                span: None,
+            });
+        }
+
+        let program = Program {
+            functions: self.functions,
+            constants: self.constants,
+            code: self.code,
+            spans: self.spans,
+        };
+        Ok((program, self.source))
+    }
+
+    fn line(&mut self, first: &Word, rest: &[Word]) -> Result<(), AsmError> {
+        if let Some(name) = first.text.strip_suffix(':') {
+            expect_operands(first, rest, 0)?;
+            if self.labels.insert(name.to_string(), self.code.len()).is_some() {
+                return Err(error(first, format!("label `{}` is defined twice", name)));
+            }
+            return Ok(());
+        }
+
+        match first.text.as_str() {
+            ".source" => {
+                expect_operands(first, rest, 1)?;
+                self.source = string(&rest[0])?;
+            }
+
+            ".loc" => {
+                expect_operands(first, rest, 2)?;
+                self.span = Some(location(&rest[0], &rest[1])?);
+            }
+
+            "fn" => {
+                expect_operands(first, rest, 2)?;
+                let name = rest[0].text.clone();
+                let index = u32::try_from(self.functions.len()).expect("too many functions");
+                if self.function_index.insert(name.clone(), index).is_some() {
+                    return Err(error(&rest[0], format!("function `{}` is defined twice", name)));
+                }
+                self.functions.push(Function {
+                    name,
+                    arity: number(&rest[1])?,
+                    entry: self.code.len(),
+                });
+            }
+
+            _ => {
+                let instr = self.instruction(first, rest)?;
+
+                let span = self.span
+                    .ok_or_else(|| error(first, "expected `.loc` before the first instruction"))?;
+                self.code.push(instr);
+                self.spans.push(span);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, first: &Word, rest: &[Word]) -> Result<Instruction, AsmError> {
+        let ip = self.code.len();
+
+        if let Some(&instr) = NO_OPERAND.iter().find(|i| mnemonic(i) == first.text) {
+            expect_operands(first, rest, 0)?;
+            return Ok(instr);
+        }
+
+        expect_operands(first, rest, 1)?;
+        let operand = &rest[0];
+
+        Ok(match first.text.as_str() {
+            "push_int" => Instruction::PushInt(number(operand)?),
+            "push_str" => {
+                let s = string(operand)?;
+                Instruction::PushConst(self.string_constant(s))
+            }
+            "load" => Instruction::LoadLocal(number(operand)?),
+            "store" => Instruction::StoreLocal(number(operand)?),
+            "call" => {
+                self.calls.push((ip, operand.clone()));
+                Instruction::Call(0)
+            }
+            "builtin" => Instruction::CallBuiltin(Builtin::lookup(&operand.text).ok_or_else(
+                || error(operand, format!("unknown built-in function `{}`", operand.text)),
+            )?),
+            "jump" => {
+                self.jumps.push((ip, operand.clone()));
+                Instruction::Jump(0)
+            }
+            "jump_if_false" => {
+                self.jumps.push((ip, operand.clone()));
+                Instruction::JumpIfFalse(0)
+            }
+            _ => return Err(error(first, format!("unknown instruction `{}`", first.text))),
+        })
+    }
+
+    fn string_constant(&mut self, s: String) -> u32 {
+        if let Some(&index) = self.string_constants.get(&s) {
+            return index;
+        }
+
+        let index = u32::try_from(self.constants.len()).expect("too many constants");
+        self.constants.push(Value::String(Rc::from(s.as_str())));
+        self.string_constants.insert(s, index);
+        index
+    }
+
+    fn label(&self, word: &Word) -> Result<usize, AsmError> {
+        match self.labels.get(&word.text) {
+            Some(&target) if target < self.code.len() => Ok(target),
+            Some(_) => Err(error(word, format!("label `{}` is not followed by an instruction", word.text))),
+            None => Err(error(word, format!("undefined label `{}`", word.text))),
+        }
+    }
+}
+
+/// Split a line into words, keeping string literals whole and dropping
+/// comments. `offset` is the byte offset of the line in the file.
+fn split_words(line: &str, offset: usize, line_number: usize) -> Result<Vec<Word>, AsmError> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == ';' {
+            break;
+        }
+
+        let mut end = start;
+        let mut in_string = false;
+        while let Some(&(i, c)) = chars.peek() {
+            if !in_string && (c.is_whitespace() || c == ';') {
+                break;
+            }
+            if c == '"' && (i == start || in_string) {
+                in_string = !in_string;
+            } else if c == '\\' && in_string {
+                chars.next();
+            }
+            chars.next();
+            end = chars.peek().map_or(line.len(), |&(i, _)| i);
+        }
+
+        let span = Span {
+            start: offset + start,
+            end: offset + end,
+            line: line_number,
+            column: line[..start].chars().count() + 1,
+        };
+        let word = Word { text: line[start..end].to_string(), span };
+        if in_string {
+            return Err(error(&word, "unterminated string"));
+        }
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+fn expect_operands(first: &Word, rest: &[Word], count: usize) -> Result<(), AsmError> {
+    if rest.len() == count {
+        return Ok(());
+    }
+    let span = rest.last().map_or(first.span, |last| first.span.to(last.span));
+    Err(AsmError {
+        message: format!(
+            "`{}` takes {} operand{}, found {}",
+            first.text,
+            count,
+            if count == 1 { "" } else { "s" },
+            rest.len()
+        ),
+        span: Some(span),
+    })
+}
+
+fn number<T: std::str::FromStr>(word: &Word) -> Result<T, AsmError> {
+    word.text
+        .parse()
+        .map_err(|_| error(word, format!("expected a number, found `{}`", word.text)))
+}
+
+/// `LINE:COLUMN` and `START..END` of a `.loc` directive.
+fn location(position: &Word, range: &Word) -> Result<Span, AsmError> {
+    let invalid = || error(position, "expected `.loc LINE:COLUMN START..END`");
+
+    let (line, column) = position.text.split_once(':').ok_or_else(invalid)?;
+    let (start, end) = range.text.split_once("..").ok_or_else(invalid)?;
+
+    let span = Span {
+        start: start.parse().map_err(|_| invalid())?,
+        end: end.parse().map_err(|_| invalid())?,
+        line: line.parse().map_err(|_| invalid())?,
+        column: column.parse().map_err(|_| invalid())?,
+    };
+    if span.line == 0 || span.column == 0 || span.start > span.end {
+        return Err(invalid());
+    }
+    Ok(span)
+}
+
+/// The contents of a string literal written by `quote`.
+fn string(word: &Word) -> Result<String, AsmError> {
+    let invalid = || error(word, format!("invalid string literal {}", word.text));
+
+    let inner = word.text
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next().ok_or_else(invalid)? {
+            '"' => '"',
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let rest = chars.as_str();
+                let hex = rest
+                    .strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .map(|(hex, _)| hex)
+                    .ok_or_else(invalid)?;
+                let c = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(invalid)?;
+                chars = rest[hex.len() + 2..].chars();
+                c
+            }
+            _ => return Err(invalid()),
+        });
+    }
+    Ok(out)
+}
+
+fn error(word: &Word, message: impl Into<String>) -> AsmError {
+    AsmError {
+        message: message.into(),
+        span: Some(word.span),
+    }
+}
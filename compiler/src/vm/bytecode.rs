@@ -1,10 +1,18 @@
-#[derive(Debug, Clone)]
+use crate::builtins::Builtin;
+use crate::span::Span;
+
+use super::value::Value;
+
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     PushInt(i64),
-    PushString(String),
-    LoadVar(String),
-    StoreVar(String),
+    PushConst(u32), // index into `Program::constants`
     PushVoid,
+
+    // Locals live on the stack; the operand is the slot relative to the
+    // current frame's base, where slot 0 is the first parameter
+    LoadLocal(u16),
+    StoreLocal(u16),
     
 
     Add,
@@ -16,30 +24,79 @@ pub enum Instruction {
     Equal,
     Greater,
 
-    Call(String, usize),
+    Call(u32), // index into `Program::functions`, which checks the arity
+    CallBuiltin(Builtin),
     Return,
 
-    Print,
     Pop,
 
     Jump(usize),
     JumpIfFalse(usize),
 
-    EnterScope,
-    ExitScope,
-
-
     Halt,
 }
 
 #[derive(Debug)]
 pub struct Function {
-    pub name: String,
+    pub name: String, // only for diagnostics
     pub arity: usize,
     pub entry: usize, // instruction index
 }
 
 pub struct Program {
     pub functions: Vec<Function>,
+    pub constants: Vec<Value>,
     pub code: Vec<Instruction>,
+    pub spans: SpanTable,
+}
+
+/// Debug info mapping each instruction back to the source it came from.
+///
+/// Consecutive instructions usually share a span (an expression's operands
+/// and operator, a statement's bookkeeping), so only the index where the
+/// span changes is stored.
+#[derive(Debug, Default)]
+pub struct SpanTable {
+    // (index of the first instruction of a run, span of the whole run)
+    runs: Vec<(usize, Span)>,
+    len: usize,
+}
+
+impl SpanTable {
+    /// Record the span of the next instruction.
+    pub fn push(&mut self, span: Span) {
+        if self.runs.last().map(|&(_, last)| last) != Some(span) {
+            self.runs.push((self.len, span));
+        }
+        self.len += 1;
+    }
+
+    /// Rebuild a table from its runs, as stored in a bytecode file. Returns
+    /// `None` unless the runs start at instruction 0, are in order and
+    /// cover exactly `len` instructions with well-formed spans.
+    pub fn from_runs(runs: Vec<(usize, Span)>, len: usize) -> Option<SpanTable> {
+        let starts_at_zero = match runs.first() {
+            Some(&(first, _)) => first == 0,
+            None => len == 0,
+        };
+        let ordered = runs.windows(2).all(|w| w[0].0 < w[1].0);
+        let in_range = runs.iter().all(|&(first, span)| {
+            first < len && span.start <= span.end && span.line >= 1 && span.column >= 1
+        });
+
+        (starts_at_zero && ordered && in_range).then_some(SpanTable { runs, len })
+    }
+
+    pub fn runs(&self) -> &[(usize, Span)] {
+        &self.runs
+    }
+
+    /// Span of the instruction at `ip`, if it was compiled from source.
+    pub fn lookup(&self, ip: usize) -> Option<Span> {
+        if ip >= self.len {
+            return None;
+        }
+        let run = self.runs.partition_point(|&(first, _)| first <= ip);
+        Some(self.runs[run - 1].1)
+    }
 }
\ No newline at end of file
@@ -1,216 +1,280 @@
-use crate::ast::{
-    Program as AstProgram,
-    FunctionDecl,
-    Block,
-    Stmt,
-    Expr,
-    BinOp,
-};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::BinOp;
+use crate::ir::{self, BlockId, Callee, Const, Reg, Terminator};
+use crate::span::Span;
 
 use super::bytecode::{
     Instruction,
     Program as BytecodeProgram,
     Function as BytecodeFunction,
+    SpanTable,
 };
+use super::value::Value;
+
+/// Generate bytecode from the IR, the same program the native backends
+/// start from, so the syntax tree is only lowered once.
+///
+/// Registers become locals. The parameters are the first slots, where the
+/// caller left the arguments, and a function starts by pushing `void` for
+/// each of its other registers. An IR instruction loads its operands,
+/// computes and stores its result, so the stack has the same height at the
+/// start of every block. A register written and read once, the read being
+/// the first operand of what comes right after the write, is left on the
+/// stack for it instead, and one never read is popped.
+pub fn compile(program: &ir::Program) -> BytecodeProgram {
+    let function_index = program.functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.name.as_str(), u32::try_from(i).expect("too many functions")))
+        .collect();
+
+    let mut compiler = BytecodeCompiler {
+        code: vec![],
+        spans: SpanTable::default(),
+        constants: vec![],
+        string_constants: HashMap::new(),
+        function_index,
+        span: Span::default(),
+    };
+
+    let functions = program.functions.iter().map(|f| compiler.function(f)).collect();
+
+    BytecodeProgram {
+        functions,
+        constants: compiler.constants,
+        code: compiler.code,
+        spans: compiler.spans,
+    }
+}
 
-pub struct BytecodeCompiler {
-    code: Vec<Instruction>,
-    functions: Vec<BytecodeFunction>,
-    loop_stack: Vec<LoopContext>,
+/// Where the value of a register is kept.
+#[derive(Clone, Copy, PartialEq)]
+enum Place {
+    Local(u16),
+    /// On top of the stack, from its definition to its only use.
+    Stack,
+    /// Nowhere: it is never read.
+    Dropped,
 }
 
-struct LoopContext {
-    start: usize,
-    breaks: Vec<usize>,
+struct BytecodeCompiler<'a> {
+    code: Vec<Instruction>,
+    spans: SpanTable,
+    constants: Vec<Value>,
+    string_constants: HashMap<String, u32>, // literal -> index into `constants`
+    function_index: HashMap<&'a str, u32>, // name -> index into `functions`
+    // Recorded for every instruction: the operation that can fail, or the
+    // function for the code before the first one
+    span: Span,
 }
 
-impl BytecodeCompiler {
-    pub fn new() -> Self {
-        Self {
-            code: vec![],
-            functions: vec![],
-            loop_stack: vec![],
-        }
+impl BytecodeCompiler<'_> {
+    fn emit(&mut self, instr: Instruction) {
+        self.code.push(instr);
+        self.spans.push(self.span);
     }
 
-    /// ENTRY POINT
-    pub fn compile(mut self, program: &AstProgram) -> BytecodeProgram {
-        // PASS 1: register function entries
-        for func in &program.functions {
-            self.functions.push(BytecodeFunction {
-                name: func.name.clone(),
-                arity: func.params.len(),
-                entry: 0, // temporary
-            });
-        }
-
-        // PASS 2: compile bodies
-        for (i, func) in program.functions.iter().enumerate() {
-            let entry = self.code.len();
-            self.functions[i].entry = entry;
-            self.compile_function(func);
-        }
+    fn function(&mut self, func: &ir::Function) -> BytecodeFunction {
+        let entry = self.code.len();
+        self.span = func.span;
 
-        BytecodeProgram {
-            functions: self.functions,
-            code: self.code,
+        let places = places(func);
+        for place in &places {
+            if let Place::Local(slot) = *place
+                && usize::from(slot) >= func.params.len()
+            {
+                self.emit(Instruction::PushVoid);
+            }
         }
-    }
+        let place = |reg: Reg| places[reg.0];
+
+        let mut block_starts = Vec::with_capacity(func.blocks.len());
+        // Jumps emitted before their target block, to patch at the end
+        let mut jumps: Vec<(usize, BlockId)> = vec![];
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            block_starts.push(self.code.len());
+
+            for instruction in &block.instructions {
+                match instruction {
+                    ir::Instruction::Const { dest, value } => {
+                        let push = match value {
+                            Const::Int(v) => Instruction::PushInt(*v),
+                            Const::Str(s) => Instruction::PushConst(self.string_constant(s)),
+                            Const::Void => Instruction::PushVoid,
+                        };
+                        self.emit(push);
+                        self.store(place(*dest));
+                    }
+
+                    ir::Instruction::Copy { dest, src } => {
+                        self.load(place(*src));
+                        self.store(place(*dest));
+                    }
+
+                    ir::Instruction::Binary { dest, op, left, right, span } => {
+                        self.span = *span;
+                        self.load(place(*left));
+                        self.load(place(*right));
+                        self.emit(match op {
+                            BinOp::Add => Instruction::Add,
+                            BinOp::Sub => Instruction::Sub,
+                            BinOp::Mul => Instruction::Mul,
+                            BinOp::Div => Instruction::Div,
+                            BinOp::Less => Instruction::Less,
+                            BinOp::Greater => Instruction::Greater,
+                            BinOp::Equal => Instruction::Equal,
+                        });
+                        self.store(place(*dest));
+                    }
+
+                    ir::Instruction::Call { dest, callee, args, span } => {
+                        self.span = *span;
+                        for arg in args {
+                            self.load(place(*arg));
+                        }
+                        self.emit(match callee {
+                            Callee::Builtin(builtin) => Instruction::CallBuiltin(*builtin),
+                            Callee::Function(name) => Instruction::Call(
+                                *self.function_index
+                                    .get(name.as_str())
+                                    .expect("calls are checked by the analyzer"),
+                            ),
+                        });
+                        self.store(place(*dest));
+                    }
+                }
+            }
 
+            // Blocks are laid out in order, so a jump to the next one is
+            // left out
+            let next = BlockId(i + 1);
+            match block.terminator {
+                Terminator::Jump(target) => {
+                    if target != next {
+                        jumps.push((self.code.len(), target));
+                        self.emit(Instruction::Jump(0));
+                    }
+                }
 
-    fn compile_function(&mut self, func: &FunctionDecl) {
-        // Create function scope
-        self.code.push(Instruction::EnterScope);
+                Terminator::Branch { condition, if_true, if_false } => {
+                    self.load(place(condition));
+                    jumps.push((self.code.len(), if_false));
+                    self.emit(Instruction::JumpIfFalse(0));
+                    if if_true != next {
+                        jumps.push((self.code.len(), if_true));
+                        self.emit(Instruction::Jump(0));
+                    }
+                }
 
-        // Bind parameters (reverse pop order)
-        for param in func.params.iter().rev() {
-            self.code.push(Instruction::StoreVar(param.clone()));
+                // `Return` discards the whole frame
+                Terminator::Return(value) => {
+                    self.load(place(value));
+                    self.emit(Instruction::Return);
+                }
+            }
         }
 
-        self.compile_block(&func.body);
+        for (ip, target) in jumps {
+            let start = block_starts[target.0];
+            self.code[ip] = match self.code[ip] {
+                Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(start),
+                _ => Instruction::Jump(start),
+            };
+        }
 
-        self.code.push(Instruction::PushVoid);
-        self.code.push(Instruction::ExitScope);
-        self.code.push(Instruction::Return);
+        BytecodeFunction {
+            name: func.name.clone(),
+            arity: func.params.len(),
+            entry,
+        }
     }
 
-
-    fn compile_block(&mut self, block: &Block) {
-        self.code.push(Instruction::EnterScope);
-
-        for stmt in &block.statements {
-            self.compile_stmt(stmt);
+    fn load(&mut self, place: Place) {
+        match place {
+            Place::Local(slot) => self.emit(Instruction::LoadLocal(slot)),
+            Place::Stack => {}
+            Place::Dropped => unreachable!("dropped registers are never read"),
         }
-
-        self.code.push(Instruction::ExitScope);
     }
 
+    fn store(&mut self, place: Place) {
+        match place {
+            Place::Local(slot) => self.emit(Instruction::StoreLocal(slot)),
+            Place::Stack => {}
+            Place::Dropped => self.emit(Instruction::Pop),
+        }
+    }
 
+    /// Index of the constant holding the string `s`, adding it to the pool
+    /// the first time it is used.
+    fn string_constant(&mut self, s: &str) -> u32 {
+        if let Some(&index) = self.string_constants.get(s) {
+            return index;
+        }
 
-    fn compile_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Let { name, value }
-            | Stmt::Assign { name, value } => {
-                self.compile_expr(value);
-                self.code.push(Instruction::StoreVar(name.clone()));
-            }
-
-            Stmt::ExprStmt(expr) => {
-                self.compile_expr(expr);
-                self.code.push(Instruction::Pop);
-            }
-
-
-            Stmt::Return(expr) => {
-                self.compile_expr(expr);
-                self.code.push(Instruction::Return);
-            }
-
-            Stmt::If {
-                condition,
-                then_block,
-                else_block,
-            } => {
-                self.compile_expr(condition);
-                let jmp_false = self.code.len();
-                self.code.push(Instruction::JumpIfFalse(0));
-
-                self.compile_block(then_block);
-
-                let jmp_end = self.code.len();
-                self.code.push(Instruction::Jump(0));
-
-                let else_start = self.code.len();
-                if let Some(b) = else_block {
-                    self.compile_block(b);
-                }
-
-                let end = self.code.len();
-                self.code[jmp_false] = Instruction::JumpIfFalse(else_start);
-                self.code[jmp_end] = Instruction::Jump(end);
-            }
-
-            Stmt::While { condition, body } => {
-                let start = self.code.len();
-
-                self.compile_expr(condition);
-                let exit = self.code.len();
-                self.code.push(Instruction::JumpIfFalse(0));
-
-                self.loop_stack.push(LoopContext {
-                    start,
-                    breaks: vec![],
-                });
-
-                self.compile_block(body);
-                self.code.push(Instruction::Jump(start));
-
-                let end = self.code.len();
-                self.code[exit] = Instruction::JumpIfFalse(end);
-
-                let ctx = self.loop_stack.pop().unwrap();
-                for b in ctx.breaks {
-                    self.code[b] = Instruction::Jump(end);
-                }
-            }
-
-            Stmt::Break => {
-                let ctx = self.loop_stack
-                    .last_mut()
-                    .expect("break outside loop");
-
-                let pos = self.code.len();
-                self.code.push(Instruction::Jump(0));
-                ctx.breaks.push(pos);
-            }
-
-            Stmt::Continue => {
-                let ctx = self.loop_stack
-                    .last()
-                    .expect("continue outside loop");
+        let index = u32::try_from(self.constants.len()).expect("too many constants");
+        self.constants.push(Value::String(Rc::from(s)));
+        self.string_constants.insert(s.to_string(), index);
+        index
+    }
+}
 
-                self.code.push(Instruction::Jump(ctx.start));
-            }
+/// Where to keep each register of `func`, indexed by register number.
+fn places(func: &ir::Function) -> Vec<Place> {
+    let mut reads = vec![0; func.register_count()];
+    let mut writes = vec![0; func.register_count()];
+    for block in &func.blocks {
+        let operands = block.instructions.iter().flat_map(|i| i.operands());
+        for reg in operands.chain(block.terminator.operand()) {
+            reads[reg.0] += 1;
+        }
+        for instruction in &block.instructions {
+            writes[instruction.dest().0] += 1;
         }
     }
 
-    fn compile_expr(&mut self, expr: &Expr) {
-        match expr {
-            Expr::IntLiteral(v) => {
-                self.code.push(Instruction::PushInt(*v));
-            }
-
-            Expr::StringLiteral(s) => {
-                self.code.push(Instruction::PushString(s.clone()));
-            }
+    let mut places: Vec<Option<Place>> = vec![None; func.register_count()];
+    for (slot, param) in func.params.iter().enumerate() {
+        places[param.0] = Some(Place::Local(slot_number(slot)));
+    }
 
-            Expr::VarRef(name) => {
-                self.code.push(Instruction::LoadVar(name.clone()));
+    for block in &func.blocks {
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            let dest = instruction.dest();
+            let next = match block.instructions.get(i + 1) {
+                Some(next) => first_operand(next),
+                None => block.terminator.operand(),
+            };
+            let once = reads[dest.0] == 1 && writes[dest.0] == 1;
+            if places[dest.0].is_none() && once && next == Some(dest) {
+                places[dest.0] = Some(Place::Stack);
             }
+        }
+    }
 
-            Expr::Binary { left, op, right } => {
-                self.compile_expr(left);
-                self.compile_expr(right);
-
-                self.code.push(match op {
-                    BinOp::Add => Instruction::Add,
-                    BinOp::Sub => Instruction::Sub,
-                    BinOp::Mul => Instruction::Mul,
-                    BinOp::Div => Instruction::Div,
-                    BinOp::Less => Instruction::Less,
-                    BinOp::Greater => Instruction::Greater,
-                    BinOp::Equal => Instruction::Equal,
-                });
+    let mut next_slot = func.params.len();
+    places
+        .into_iter()
+        .enumerate()
+        .map(|(reg, place)| match place {
+            Some(place) => place,
+            None if reads[reg] == 0 => Place::Dropped,
+            None => {
+                next_slot += 1;
+                Place::Local(slot_number(next_slot - 1))
             }
+        })
+        .collect()
+}
 
-            Expr::Call { name, args } => {
-                for arg in args {
-                    self.compile_expr(arg);
-                }
-
-                self.code.push(Instruction::Call(name.clone(), args.len()));
-            }
+/// The register `instruction` loads first.
+fn first_operand(instruction: &ir::Instruction) -> Option<Reg> {
+    instruction.operands().first().copied()
+}
 
-        }
-    }
-}
\ No newline at end of file
+fn slot_number(slot: usize) -> u16 {
+    u16::try_from(slot).expect("too many registers in one function")
+}
@@ -1,7 +1,14 @@
-mod bytecode;
+pub mod bytecode;
 mod compiler;
+mod optimize;
+pub mod asm;
+pub mod nxc;
 mod value;
+mod verify;
+#[allow(clippy::module_inception)]
 mod vm;
 
 pub use compiler::*;
+pub use optimize::optimize;
+pub use value::Value;
 pub use vm::*;
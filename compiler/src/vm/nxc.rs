@@ -0,0 +1,313 @@
+//! The `.nxc` file format: a compiled program that can be run without its
+//! source.
+//!
+//! All integers are little-endian. Strings are a `u32` byte length
+//! followed by UTF-8.
+//!
+//! ```text
+//! magic      b"NXC\0"
+//! version    u16                       (FORMAT_VERSION)
+//! source     string                    (file the program was built from)
+//! constants  u32 count, then per constant: tag u8 (0 int i64, 1 string)
+//! functions  u32 count, then per function: name string, arity u32, entry u32
+//! code       u32 count, then per instruction: opcode u8 and its operands
+//! spans      u32 count, then per run: first u32, start u32, end u32,
+//!            line u32, column u32
+//! ```
+
+use std::rc::Rc;
+
+use crate::builtins::Builtin;
+use crate::span::Span;
+
+use super::bytecode::{Function, Instruction, Program, SpanTable};
+use super::value::Value;
+
+const MAGIC: &[u8; 4] = b"NXC\0";
+
+/// Bump whenever the layout or the meaning of an opcode changes.
+const FORMAT_VERSION: u16 = 1;
+
+/// Why a `.nxc` file was rejected.
+#[derive(Debug)]
+pub struct LoadError {
+    pub message: String,
+}
+
+impl LoadError {
+    fn new(message: impl Into<String>) -> Self {
+        LoadError { message: message.into() }
+    }
+}
+
+/* ===========================
+   Encoding
+=========================== */
+
+/// Serialize `program`, recording `source` as the file it was built from.
+pub fn encode(program: &Program, source: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_str(&mut out, source);
+
+    write_len(&mut out, program.constants.len());
+    for constant in &program.constants {
+        match constant {
+            Value::Int(v) => {
+                out.push(0);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::String(s) => {
+                out.push(1);
+                write_str(&mut out, s);
+            }
+            Value::Void => unreachable!("void is never a constant"),
+        }
+    }
+
+    write_len(&mut out, program.functions.len());
+    for function in &program.functions {
+        write_str(&mut out, &function.name);
+        write_len(&mut out, function.arity);
+        write_len(&mut out, function.entry);
+    }
+
+    write_len(&mut out, program.code.len());
+    for instr in &program.code {
+        write_instruction(&mut out, instr);
+    }
+
+    let runs = program.spans.runs();
+    write_len(&mut out, runs.len());
+    for &(first, span) in runs {
+        write_len(&mut out, first);
+        for field in [span.start, span.end, span.line, span.column] {
+            write_len(&mut out, field);
+        }
+    }
+
+    out
+}
+
+fn write_instruction(out: &mut Vec<u8>, instr: &Instruction) {
+    match *instr {
+        Instruction::PushInt(v) => {
+            out.push(0);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        Instruction::PushConst(index) => {
+            out.push(1);
+            out.extend_from_slice(&index.to_le_bytes());
+        }
+        Instruction::PushVoid => out.push(2),
+        Instruction::LoadLocal(slot) => {
+            out.push(3);
+            out.extend_from_slice(&slot.to_le_bytes());
+        }
+        Instruction::StoreLocal(slot) => {
+            out.push(4);
+            out.extend_from_slice(&slot.to_le_bytes());
+        }
+        Instruction::Add => out.push(5),
+        Instruction::Sub => out.push(6),
+        Instruction::Mul => out.push(7),
+        Instruction::Div => out.push(8),
+        Instruction::Less => out.push(9),
+        Instruction::Equal => out.push(10),
+        Instruction::Greater => out.push(11),
+        Instruction::Call(function) => {
+            out.push(12);
+            out.extend_from_slice(&function.to_le_bytes());
+        }
+        Instruction::CallBuiltin(builtin) => {
+            out.push(13);
+            out.push(builtin.id());
+        }
+        Instruction::Return => out.push(14),
+        Instruction::Pop => out.push(15),
+        Instruction::Jump(target) => {
+            out.push(16);
+            write_len(out, target);
+        }
+        Instruction::JumpIfFalse(target) => {
+            out.push(17);
+            write_len(out, target);
+        }
+        Instruction::Halt => out.push(18),
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("program too large for the .nxc format");
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/* ===========================
+   Decoding
+=========================== */
+
+/// Load a program written by `encode`, returning it with the name of the
+/// source file it was built from.
+///
+/// The file is checked to be complete and internally consistent: every
+/// opcode is known and every jump target, function entry, call and
+/// constant index is in range.
+pub fn decode(bytes: &[u8]) -> Result<(Program, String), LoadError> {
+    let mut r = Reader { bytes, pos: 0 };
+
+    if r.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(LoadError::new("not a nexo bytecode file"));
+    }
+
+    let version = u16::from_le_bytes(r.array()?);
+    if version != FORMAT_VERSION {
+        return Err(LoadError::new(format!(
+            "unsupported format version {} (this nexo reads version {}); rebuild it from source",
+            version, FORMAT_VERSION
+        )));
+    }
+
+    let source = r.string()?;
+
+    let mut constants = Vec::new();
+    for _ in 0..r.u32()? {
+        constants.push(match r.u8()? {
+            0 => Value::Int(i64::from_le_bytes(r.array()?)),
+            1 => Value::String(Rc::from(r.string()?)),
+            tag => return Err(LoadError::new(format!("unknown constant tag {}", tag))),
+        });
+    }
+
+    let mut functions = Vec::new();
+    for _ in 0..r.u32()? {
+        functions.push(Function {
+            name: r.string()?,
+            arity: r.u32()? as usize,
+            entry: r.u32()? as usize,
+        });
+    }
+
+    let mut code = Vec::new();
+    for _ in 0..r.u32()? {
+        code.push(read_instruction(&mut r)?);
+    }
+
+    let mut runs = Vec::new();
+    for _ in 0..r.u32()? {
+        let first = r.u32()? as usize;
+        let [start, end, line, column] = [r.u32()?, r.u32()?, r.u32()?, r.u32()?];
+        runs.push((
+            first,
+            Span {
+                start: start as usize,
+                end: end as usize,
+                line: line as usize,
+                column: column as usize,
+            },
+        ));
+    }
+    let spans = SpanTable::from_runs(runs, code.len())
+        .ok_or_else(|| LoadError::new("malformed debug info"))?;
+
+    if r.pos != bytes.len() {
+        return Err(LoadError::new("unexpected data after the end of the program"));
+    }
+
+    for function in &functions {
+        if function.entry >= code.len() {
+            return Err(LoadError::new(format!(
+                "function `{}` starts outside the code",
+                function.name
+            )));
+        }
+    }
+
+    for (ip, instr) in code.iter().enumerate() {
+        let valid = match *instr {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => target < code.len(),
+            Instruction::Call(function) => (function as usize) < functions.len(),
+            Instruction::PushConst(index) => (index as usize) < constants.len(),
+            _ => true,
+        };
+        if !valid {
+            return Err(LoadError::new(format!(
+                "instruction {} ({:?}) refers outside the program",
+                ip, instr
+            )));
+        }
+    }
+
+    Ok((Program { functions, constants, code, spans }, source))
+}
+
+fn read_instruction(r: &mut Reader) -> Result<Instruction, LoadError> {
+    let opcode = r.u8()?;
+
+    Ok(match opcode {
+        0 => Instruction::PushInt(i64::from_le_bytes(r.array()?)),
+        1 => Instruction::PushConst(r.u32()?),
+        2 => Instruction::PushVoid,
+        3 => Instruction::LoadLocal(u16::from_le_bytes(r.array()?)),
+        4 => Instruction::StoreLocal(u16::from_le_bytes(r.array()?)),
+        5 => Instruction::Add,
+        6 => Instruction::Sub,
+        7 => Instruction::Mul,
+        8 => Instruction::Div,
+        9 => Instruction::Less,
+        10 => Instruction::Equal,
+        11 => Instruction::Greater,
+        12 => Instruction::Call(r.u32()?),
+        13 => {
+            let id = r.u8()?;
+            let builtin = Builtin::from_id(id)
+                .ok_or_else(|| LoadError::new(format!("unknown built-in function {}", id)))?;
+            Instruction::CallBuiltin(builtin)
+        }
+        14 => Instruction::Return,
+        15 => Instruction::Pop,
+        16 => Instruction::Jump(r.u32()? as usize),
+        17 => Instruction::JumpIfFalse(r.u32()? as usize),
+        18 => Instruction::Halt,
+        _ => return Err(LoadError::new(format!("unknown opcode {}", opcode))),
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self.bytes
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| LoadError::new("unexpected end of file"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::new("string is not valid UTF-8"))
+    }
+}
@@ -0,0 +1,137 @@
+use super::bytecode::{Instruction, Program, SpanTable};
+
+/// Peephole optimizations over compiled bytecode (`-O`), repeated until
+/// none of them applies. Constants and dead code are already dealt with in
+/// the IR; what is left is the jumps between its blocks:
+///
+/// - jumps to a `Jump` go straight to its target, and jumps to the next
+///   instruction are dropped
+/// - unreachable code is removed, such as a block that only jumped on
+pub fn optimize(program: &mut Program) {
+    loop {
+        let rewritten = rewrite(program);
+        let removed = remove_unreachable(program);
+        if !rewritten && !removed {
+            break;
+        }
+    }
+}
+
+fn rewrite(program: &mut Program) -> bool {
+    let code = &mut program.code;
+    let len = code.len();
+
+    let mut changed = false;
+    let mut removed = vec![false; len];
+
+    for ip in 0..len {
+        // Jump threading
+        if let Instruction::Jump(t) | Instruction::JumpIfFalse(t) = code[ip] {
+            let target = final_target(code, t);
+            if target != t {
+                code[ip] = retarget(code[ip], target);
+                changed = true;
+            }
+        }
+
+        match code[ip] {
+            Instruction::Jump(t) if t == ip + 1 => {
+                removed[ip] = true;
+                changed = true;
+            }
+
+            Instruction::JumpIfFalse(t) if t == ip + 1 => {
+                code[ip] = Instruction::Pop;
+                changed = true;
+            }
+
+            _ => {}
+        }
+    }
+
+    if removed.contains(&true) {
+        compact(program, &removed);
+    }
+    changed
+}
+
+/// Where a jump to `target` ends up after following any chain of `Jump`s.
+fn final_target(code: &[Instruction], mut target: usize) -> usize {
+    // A chain longer than the code is a loop with no way out
+    for _ in 0..code.len() {
+        match code[target] {
+            Instruction::Jump(next) if next != target => target = next,
+            _ => break,
+        }
+    }
+    target
+}
+
+fn retarget(instr: Instruction, target: usize) -> Instruction {
+    match instr {
+        Instruction::Jump(_) => Instruction::Jump(target),
+        Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+        other => other,
+    }
+}
+
+fn remove_unreachable(program: &mut Program) -> bool {
+    let code = &program.code;
+    let mut reachable = vec![false; code.len()];
+    let mut worklist: Vec<usize> = program.functions.iter().map(|f| f.entry).collect();
+
+    while let Some(ip) = worklist.pop() {
+        if ip >= code.len() || reachable[ip] {
+            continue;
+        }
+        reachable[ip] = true;
+
+        match code[ip] {
+            Instruction::Return | Instruction::Halt => {}
+            Instruction::Jump(t) => worklist.push(t),
+            Instruction::JumpIfFalse(t) => worklist.extend([ip + 1, t]),
+            _ => worklist.push(ip + 1),
+        }
+    }
+
+    let removed: Vec<bool> = reachable.iter().map(|r| !r).collect();
+    if !removed.contains(&true) {
+        return false;
+    }
+    compact(program, &removed);
+    true
+}
+
+/// Delete the `removed` instructions. Jumps and function entries that
+/// pointed at a removed instruction move to the next one that is kept.
+fn compact(program: &mut Program, removed: &[bool]) {
+    let mut new_index = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+    for &r in removed {
+        new_index.push(kept);
+        if !r {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let mut code = Vec::with_capacity(kept);
+    let mut spans = SpanTable::default();
+
+    for (ip, &instr) in program.code.iter().enumerate() {
+        if removed[ip] {
+            continue;
+        }
+        code.push(match instr {
+            Instruction::Jump(t) | Instruction::JumpIfFalse(t) => retarget(instr, new_index[t]),
+            other => other,
+        });
+        spans.push(program.spans.lookup(ip).expect("every instruction has a span"));
+    }
+
+    for f in &mut program.functions {
+        f.entry = new_index[f.entry];
+    }
+    program.code = code;
+    program.spans = spans;
+}
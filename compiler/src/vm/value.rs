@@ -1,8 +1,10 @@
+use std::rc::Rc;
+
+/// A runtime value. Cloning is cheap: strings are shared, not copied.
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub enum Value {
     Int(i64),
-    String(String),
+    String(Rc<str>),
     Void,
 }
 
@@ -14,4 +16,12 @@ impl Value {
             _ => false,
         }
     }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::String(_) => "string",
+            Value::Void => "void",
+        }
+    }
 }
@@ -0,0 +1,179 @@
+use crate::diagnostic::{arguments, E_INVALID_BYTECODE};
+use crate::runtime_error::RuntimeError;
+
+use super::bytecode::{Function, Instruction, Program};
+
+/// Check that `program` is safe to run before the VM executes any of it.
+///
+/// Each function is interpreted abstractly, tracking only the height of
+/// its stack window (parameters and locals included) along every path:
+///
+/// - jumps stay inside the function
+/// - no instruction pops more than the frame holds, and locals are in range
+/// - every path reaching an instruction reaches it with the same height
+/// - calls name an existing function and pass it the arguments it expects
+/// - every path ends in `Return`
+pub fn verify(program: &Program) -> Result<(), RuntimeError> {
+    let mut entries: Vec<usize> = program.functions.iter().map(|f| f.entry).collect();
+    entries.sort();
+    entries.dedup();
+
+    for function in &program.functions {
+        if function.entry >= program.code.len() {
+            return Err(RuntimeError::new(
+                E_INVALID_BYTECODE,
+                &format!("function `{}` starts outside the code", function.name),
+            ));
+        }
+
+        // A function's code runs up to the next function's entry
+        let end = entries
+            .iter()
+            .find(|&&entry| entry > function.entry)
+            .map_or(program.code.len(), |&entry| entry);
+
+        Verifier { program, function, end }.run()?;
+    }
+
+    Ok(())
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    function: &'a Function,
+    end: usize,
+}
+
+impl Verifier<'_> {
+    fn run(&self) -> Result<(), RuntimeError> {
+        let start = self.function.entry;
+
+        // Stack height on entry to each instruction, once a path reaches it
+        let mut heights: Vec<Option<usize>> = vec![None; self.end - start];
+        let mut worklist = vec![(start, self.function.arity)];
+
+        while let Some((ip, height)) = worklist.pop() {
+            match heights[ip - start] {
+                Some(seen) if seen == height => continue,
+                Some(seen) => {
+                    return Err(self.error(
+                        ip,
+                        &format!("is reached with different stack heights ({} and {})", seen, height),
+                    ));
+                }
+                None => heights[ip - start] = Some(height),
+            }
+
+            let (pops, pushes) = self.effect(ip, height)?;
+            if pops > height {
+                return Err(self.error(ip, "pops from an empty stack"));
+            }
+            let height = height - pops + pushes;
+
+            for next in self.successors(ip)? {
+                if next >= self.end {
+                    return Err(self.error(ip, "runs past the end of the function without returning"));
+                }
+                worklist.push((next, height));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How many values the instruction at `ip` pops and then pushes.
+    fn effect(&self, ip: usize, height: usize) -> Result<(usize, usize), RuntimeError> {
+        Ok(match self.program.code[ip] {
+            Instruction::PushInt(_) | Instruction::PushVoid => (0, 1),
+
+            Instruction::PushConst(index) => {
+                if index as usize >= self.program.constants.len() {
+                    return Err(self.error(ip, "refers to a missing constant"));
+                }
+                (0, 1)
+            }
+
+            Instruction::LoadLocal(slot) => {
+                if slot as usize >= height {
+                    return Err(self.error(ip, "reads a local outside the frame"));
+                }
+                (0, 1)
+            }
+
+            Instruction::StoreLocal(slot) => {
+                if slot as usize + 1 >= height {
+                    return Err(self.error(ip, "writes a local outside the frame"));
+                }
+                (1, 0)
+            }
+
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::Less
+            | Instruction::Equal
+            | Instruction::Greater => (2, 1),
+
+            Instruction::Call(index) => {
+                let callee = self.program.functions
+                    .get(index as usize)
+                    .ok_or_else(|| self.error(ip, "calls a function that does not exist"))?;
+                if callee.arity > height {
+                    return Err(self.error(
+                        ip,
+                        &format!(
+                            "calls `{}` with {} on the stack, but it takes {}",
+                            callee.name,
+                            values(height),
+                            arguments(callee.arity)
+                        ),
+                    ));
+                }
+                (callee.arity, 1)
+            }
+
+            Instruction::CallBuiltin(builtin) => (builtin.arity(), 1),
+
+            Instruction::Return | Instruction::Halt => (1, 0),
+
+            Instruction::Pop | Instruction::JumpIfFalse(_) => (1, 0),
+
+            Instruction::Jump(_) => (0, 0),
+        })
+    }
+
+    /// Instructions that can run after the one at `ip`.
+    fn successors(&self, ip: usize) -> Result<Vec<usize>, RuntimeError> {
+        let target = |target: usize| {
+            if (self.function.entry..self.end).contains(&target) {
+                Ok(target)
+            } else {
+                Err(self.error(ip, "jumps outside the function"))
+            }
+        };
+
+        Ok(match self.program.code[ip] {
+            Instruction::Return | Instruction::Halt => vec![],
+            Instruction::Jump(t) => vec![target(t)?],
+            Instruction::JumpIfFalse(t) => vec![ip + 1, target(t)?],
+            _ => vec![ip + 1],
+        })
+    }
+
+    fn error(&self, ip: usize, problem: &str) -> RuntimeError {
+        let mut error = RuntimeError::new(
+            E_INVALID_BYTECODE,
+            &format!(
+                "invalid bytecode in `{}`: instruction {} (`{:?}`) {}",
+                self.function.name, ip, self.program.code[ip], problem
+            ),
+        );
+        error.span = self.program.spans.lookup(ip);
+        error
+    }
+}
+
+fn values(count: usize) -> String {
+    format!("{} value{}", count, if count == 1 { "" } else { "s" })
+}
@@ -1,151 +1,161 @@
-use std::collections::HashMap;
+use crate::arith;
+use crate::ast::BinOp;
+use crate::builtins::Builtin;
+use crate::diagnostic::*;
+use crate::runtime_error::{RuntimeError, TraceFrame};
 
-use super::bytecode::{Instruction, Program};
+use super::bytecode::{Function, Instruction, Program, SpanTable};
 use super::value::Value;
+use super::verify::verify;
+
+/// Calls nested deeper than this are reported as a stack overflow instead
+/// of exhausting memory.
+pub const MAX_FRAMES: usize = 10_000;
 
 #[derive(Debug)]
 struct CallFrame {
+    function: usize, // index into `VM::functions`
     return_ip: usize,
-    base: usize,
-    scopes: Vec<HashMap<String, Value>>,
+    base: usize, // stack index of the frame's slot 0
 }
 
 pub struct VM {
     ip: usize,
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
     frames: Vec<CallFrame>,
-    functions: HashMap<String, (usize, usize)>, // name -> (entry, arity)
+    functions: Vec<Function>,
+    constants: Vec<Value>,
     code: Vec<Instruction>,
+    spans: SpanTable,
 }
 
 impl VM {
-    pub fn new(program: Program) -> Self {
-        let mut functions = HashMap::new();
-        for f in program.functions {
-            functions.insert(f.name, (f.entry, f.arity));
-        }
-
-        let main_arity = functions
-            .get("main")
-            .map(|(_, arity)| *arity)
-            .expect("No 'main' function defined");
-
-
-        if main_arity != 0 {
-            panic!("main() must not take arguments");
+    pub fn new(program: Program) -> Result<Self, RuntimeError> {
+        verify(&program)?;
+
+        let main = program.functions
+            .iter()
+            .position(|f| f.name == "main")
+            .ok_or_else(|| RuntimeError::new(E_MISSING_MAIN, "no `main` function defined"))?;
+
+        if program.functions[main].arity != 0 {
+            return Err(RuntimeError::new(
+                E_MAIN_WITH_PARAMS,
+                "`main` must not take parameters",
+            ));
         }
 
         let mut code = program.code;
         let entry_ip = code.len();
 
-        code.push(Instruction::Call("main".into(), 0));
+        code.push(Instruction::Call(main as u32));
         code.push(Instruction::Halt);
 
-        VM {
+        Ok(VM {
             ip: entry_ip,
             stack: vec![],
-            globals: HashMap::new(),
             frames: vec![],
-            functions,
+            functions: program.functions,
+            constants: program.constants,
             code,
+            spans: program.spans,
+        })
+    }
+
+    /// Stack index of local `slot` in the current frame.
+    fn local(&self, slot: u16) -> Result<usize, RuntimeError> {
+        let frame = self.frames
+            .last()
+            .ok_or_else(|| invalid("local access outside function"))?;
+
+        let index = frame.base + slot as usize;
+        if index >= self.stack.len() {
+            return Err(invalid("local slot out of range"));
         }
+        Ok(index)
     }
 
-    fn current_frame(&self) -> Option<&CallFrame> {
-        self.frames.last()
+    /// Run `main` to completion and return its result.
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        self.execute().map_err(|e| self.with_trace(e))
     }
 
-    fn current_frame_mut(&mut self) -> Option<&mut CallFrame> {
-        self.frames.last_mut()
+    /// Attach the source location of the failing instruction and the Nexo
+    /// call stack leading to it to `error`.
+    fn with_trace(&self, mut error: RuntimeError) -> RuntimeError {
+        let mut ip = self.ip;
+        error.span = self.spans.lookup(ip);
+
+        for frame in self.frames.iter().rev() {
+            error.trace.push(TraceFrame {
+                function: self.functions[frame.function].name.clone(),
+                span: self.spans.lookup(ip),
+            });
+            // The caller is suspended at its `Call` instruction
+            ip = frame.return_ip - 1;
+        }
+
+        error
     }
 
-    pub fn run(&mut self) {
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
         loop {
-            let instr = self.code[self.ip].clone();
+            let instr = self.code[self.ip];
 
             match instr {
                 Instruction::PushInt(v) => {
                     self.stack.push(Value::Int(v));
                 }
 
-                Instruction::PushString(s) => {
-                    self.stack.push(Value::String(s));
-                }
-
-                Instruction::LoadVar(name) => {
-                    let mut value = None;
-
-                    if let Some(frame) = self.current_frame() {
-                        for scope in frame.scopes.iter().rev() {
-                            if let Some(v) = scope.get(&name) {
-                                value = Some(v.clone());
-                                break;
-                            }
-                        }
-                    }
-
-                    let v = value.unwrap_or_else(|| {
-                        self.globals
-                            .get(&name)
-                            .cloned()
-                            .unwrap_or_else(|| panic!("undefined variable '{}'", name))
-                    });
-
-                    self.stack.push(v);
-                }
-
-                Instruction::StoreVar(name) => {
-                    let v = self.stack.pop().expect("stack underflow");
-
-                    if let Some(frame) = self.current_frame_mut() {
-                        frame.scopes
-                            .last_mut()
-                            .expect("no scope")
-                            .insert(name, v);
-                    } else {
-                        self.globals.insert(name, v);
-                    }
+                Instruction::PushConst(index) => {
+                    let v = self.constants
+                        .get(index as usize)
+                        .ok_or_else(|| invalid("constant index out of range"))?;
+                    self.stack.push(v.clone());
                 }
 
-                Instruction::EnterScope => {
-                    if let Some(frame) = self.current_frame_mut() {
-                        frame.scopes.push(HashMap::new());
-                    }
+                Instruction::LoadLocal(slot) => {
+                    let index = self.local(slot)?;
+                    self.stack.push(self.stack[index].clone());
                 }
 
-                Instruction::ExitScope => {
-                    if let Some(frame) = self.current_frame_mut() {
-                        frame.scopes.pop();
-                    }
+                Instruction::StoreLocal(slot) => {
+                    let v = pop(&mut self.stack)?;
+                    let index = self.local(slot)?;
+                    self.stack[index] = v;
                 }
 
-                Instruction::Add => binop(&mut self.stack, |a, b| a + b),
-                Instruction::Sub => binop(&mut self.stack, |a, b| a - b),
-                Instruction::Mul => binop(&mut self.stack, |a, b| a * b),
-                Instruction::Div => binop(&mut self.stack, |a, b| a / b),
+                Instruction::Add => binop(&mut self.stack, BinOp::Add)?,
+                Instruction::Sub => binop(&mut self.stack, BinOp::Sub)?,
+                Instruction::Mul => binop(&mut self.stack, BinOp::Mul)?,
+                Instruction::Div => binop(&mut self.stack, BinOp::Div)?,
 
-                Instruction::Less => cmpop(&mut self.stack, |a, b| a < b),
-                Instruction::Greater => cmpop(&mut self.stack, |a, b| a > b),
-                Instruction::Equal => cmpop(&mut self.stack, |a, b| a == b),
+                Instruction::Less => binop(&mut self.stack, BinOp::Less)?,
+                Instruction::Greater => binop(&mut self.stack, BinOp::Greater)?,
+                Instruction::Equal => binop(&mut self.stack, BinOp::Equal)?,
 
-                Instruction::Print => {
-                    let v = self.stack.pop().expect("stack underflow");
+                Instruction::CallBuiltin(Builtin::Print) => {
+                    let v = pop(&mut self.stack)?;
                     match v {
                         Value::Int(i) => println!("{}", i),
                         Value::String(s) => println!("{}", s),
-                        Value::Void => println!(""),
+                        Value::Void => println!(),
                     }
                     self.stack.push(Value::Void);
                 }
 
+                Instruction::CallBuiltin(builtin) => {
+                    let (a, b) = pop_ints(&mut self.stack, builtin.name())?;
+                    self.stack.push(Value::Int(builtin.apply_int(a, b)));
+                }
+
                 Instruction::Jump(pos) => {
                     self.ip = pos;
                     continue;
                 }
 
                 Instruction::JumpIfFalse(pos) => {
-                    let v = self.stack.pop().expect("stack underflow");
+                    let v = pop(&mut self.stack)?;
                     if !v.is_truthy() {
                         self.ip = pos;
                         continue;
@@ -160,33 +170,28 @@ impl VM {
                     self.stack.push(Value::Void);
                 }
 
-                Instruction::Call(name, argc) => {
-                    // builtin: print
-                    if name == "print" {
-                        let v = self.stack.pop().expect("print expects value");
-                        match v {
-                            Value::Int(i) => println!("{}", i),
-                            Value::String(s) => println!("{}", s),
-                            Value::Void => println!(""),
-                        }
-                        self.stack.push(Value::Void);
-                        self.ip += 1;
-                        continue;
+                Instruction::Call(function) => {
+                    let function = function as usize;
+                    let Function { entry, arity, .. } = *self.functions
+                        .get(function)
+                        .ok_or_else(|| invalid("call to undefined function index"))?;
+
+                    if self.frames.len() >= MAX_FRAMES {
+                        return Err(RuntimeError::new(
+                            E_STACK_OVERFLOW,
+                            &format!("stack overflow: more than {} nested calls", MAX_FRAMES),
+                        ));
                     }
 
-                    let (entry, arity) = self.functions
-                        .get(&name)
-                        .unwrap_or_else(|| panic!("undefined function '{}'", name))
-                        .clone();
-
-                    if argc != arity {
-                        panic!("arity mismatch in call to '{}'", name);
-                    }
+                    let base = self.stack
+                        .len()
+                        .checked_sub(arity)
+                        .ok_or_else(|| invalid("stack underflow"))?;
 
                     let frame = CallFrame {
+                        function,
                         return_ip: self.ip + 1,
-                        base: self.stack.len() - argc,
-                        scopes: vec![HashMap::new()],
+                        base,
                     };
 
                     self.frames.push(frame);
@@ -200,7 +205,7 @@ impl VM {
 
                     let frame = self.frames
                         .pop()
-                        .expect("return outside function");
+                        .ok_or_else(|| invalid("return outside function"))?;
 
                     self.stack.truncate(frame.base);
                     self.stack.push(result);
@@ -209,7 +214,9 @@ impl VM {
                     continue;
                 }
 
-                Instruction::Halt => break,
+                Instruction::Halt => {
+                    return Ok(self.stack.pop().unwrap_or(Value::Void));
+                }
             }
 
             self.ip += 1;
@@ -222,21 +229,35 @@ impl VM {
    Helpers
 =========================== */
 
-fn pop_int(stack: &mut Vec<Value>) -> i64 {
-    match stack.pop().expect("stack underflow") {
-        Value::Int(v) => v,
-        _ => panic!("expected int"),
-    }
+fn invalid(message: &str) -> RuntimeError {
+    RuntimeError::new(E_INVALID_BYTECODE, message)
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, RuntimeError> {
+    stack.pop().ok_or_else(|| invalid("stack underflow"))
 }
 
-fn binop(stack: &mut Vec<Value>, f: fn(i64, i64) -> i64) {
-    let b = pop_int(stack);
-    let a = pop_int(stack);
-    stack.push(Value::Int(f(a, b)));
+/// Pop the two operands of `op`, which must both be ints.
+fn pop_ints(stack: &mut Vec<Value>, op: &str) -> Result<(i64, i64), RuntimeError> {
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok((a, b)),
+        (a, b) => Err(RuntimeError::new(
+            E_TYPE_MISMATCH,
+            &format!(
+                "unsupported operand types for `{}`: {} and {}",
+                op,
+                a.type_name(),
+                b.type_name()
+            ),
+        )),
+    }
 }
 
-fn cmpop(stack: &mut Vec<Value>, f: fn(i64, i64) -> bool) {
-    let b = pop_int(stack);
-    let a = pop_int(stack);
-    stack.push(Value::Int(f(a, b) as i64));
+fn binop(stack: &mut Vec<Value>, op: BinOp) -> Result<(), RuntimeError> {
+    let (a, b) = pop_ints(stack, op.symbol())?;
+    stack.push(Value::Int(arith::apply(&op, a, b)?));
+    Ok(())
 }
@@ -0,0 +1,503 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::BinOp;
+use crate::builtins::Builtin;
+use crate::diagnostic::{E_DIVISION_BY_ZERO, E_STACK_OVERFLOW};
+use crate::ir::{self, BlockId, Callee, Const, Function, Instruction, Reg, Terminator, Type};
+use crate::span::Span;
+use crate::vm::MAX_FRAMES;
+
+/// Printing and error reporting for generated programs, written in
+/// assembly so that building one needs an assembler and linker but no C.
+const RUNTIME: &str = include_str!("../runtime/nexo_rt.s");
+
+/// Stack left below the innermost Nexo call for the C library.
+const C_STACK: usize = 1 << 20;
+
+const TAG_INT: i64 = 0;
+const TAG_STRING: i64 = 1;
+const TAG_VOID: i64 = 2;
+
+/// Translates the IR of a checked program to x86-64 assembly for the GNU
+/// assembler (AT&T syntax, System V ABI), linked against the C library
+/// with `cc`.
+///
+/// A value is a tag and a payload, kept in `%rdx` and `%rax` while it is
+/// being computed and in a 16-byte stack slot otherwise: the tag at the
+/// slot's address and the payload 8 bytes above it. Each IR register lives
+/// in a slot, shared with other registers whose values are never needed
+/// at the same time. Nexo functions take their arguments pushed on the
+/// stack, first argument deepest, and return in `%rax`/`%rdx`. The program
+/// runs on a stack sized from its largest frame, so it reaches the call
+/// limit before running out.
+pub struct X86Generator {
+    output: String,
+    source_name: String,
+    /// String literals in the read-only section, by contents.
+    strings: HashMap<String, String>,
+    /// Counter for unique labels in the whole program.
+    next_label: usize,
+
+    /// Code of the function being generated, and its error paths, which
+    /// go after it so the common case falls through.
+    code: String,
+    errors: String,
+    /// Position of the function in the program, for block labels.
+    function: usize,
+    /// Frame offset of each register's slot.
+    offsets: Vec<i64>,
+    types: Vec<Type>,
+    /// Bytes of stack the largest function call takes, arguments included.
+    largest_frame: usize,
+}
+
+impl X86Generator {
+    pub fn new(source_name: &str) -> Self {
+        X86Generator {
+            output: String::new(),
+            source_name: source_name.to_string(),
+            strings: HashMap::new(),
+            next_label: 0,
+            code: String::new(),
+            errors: String::new(),
+            function: 0,
+            offsets: Vec::new(),
+            types: Vec::new(),
+            largest_frame: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &ir::Program) -> String {
+        self.output.push_str(RUNTIME);
+        self.output.push('\n');
+
+        for (i, func) in program.functions.iter().enumerate() {
+            self.function = i;
+            self.emit_function(func);
+        }
+        self.emit_main();
+        self.emit_data();
+        self.output
+    }
+
+    fn emit_main(&mut self) {
+        // Room for `MAX_FRAMES` of the largest frame, and for the C library
+        // printing from the innermost one
+        let stack = MAX_FRAMES * self.largest_frame + C_STACK;
+
+        self.output.push_str("    .globl main\nmain:\n");
+        self.output.push_str("    pushq %rbp\n    movq %rsp, %rbp\n");
+        // `main`'s own frame counts toward the call limit
+        self.output.push_str("    movq $1, nx_depth(%rip)\n");
+        self.output.push_str(&format!("    movq ${}, %rdi\n", stack));
+        self.output.push_str("    call nx_run_main\n");
+        self.output.push_str("    xorl %eax, %eax\n    popq %rbp\n    ret\n\n");
+    }
+
+    fn emit_data(&mut self) {
+        self.output.push_str("    .section .rodata\nnx_source:\n");
+        let source = asm_string(&self.source_name);
+        self.output.push_str(&format!("    .string {}\n", source));
+
+        let mut strings: Vec<(&String, &String)> =
+            self.strings.iter().map(|(value, label)| (label, value)).collect();
+        strings.sort();
+        for (label, value) in strings {
+            self.output.push_str(&format!("{}:\n    .string {}\n", label, asm_string(value)));
+        }
+
+        // Nothing here needs an executable stack
+        self.output.push_str("    .section .note.GNU-stack,\"\",@progbits\n");
+    }
+
+    fn emit_function(&mut self, func: &Function) {
+        let (slots, count) = assign_slots(func);
+        let params = func.params.len() as i64;
+        self.offsets = (0..func.register_count())
+            .map(|r| match func.params.iter().position(|p| p.0 == r) {
+                // Above the saved `%rbp` and the return address, last argument first
+                Some(i) => 16 + 16 * (params - 1 - i as i64),
+                None => -16 * (slots[r] as i64 + 1),
+            })
+            .collect();
+        self.types = func.types.clone();
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            let label = self.block_label(BlockId(i));
+            self.place(&label);
+            for instruction in &block.instructions {
+                self.emit_instruction(instruction);
+            }
+            self.emit_terminator(&block.terminator, BlockId(i + 1));
+        }
+
+        // The return address, the saved `%rbp`, the slots and the
+        // arguments of the largest call made from here
+        let arguments = func.blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .map(|instruction| match instruction {
+                Instruction::Call { callee: Callee::Function(_), args, .. } => args.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        self.largest_frame = self.largest_frame.max(16 * (1 + count + arguments));
+
+        self.output.push_str(&format!("    .text\nf_{}:\n", func.name));
+        self.output.push_str("    pushq %rbp\n    movq %rsp, %rbp\n");
+        if count > 0 {
+            // A multiple of 16 keeps the stack aligned for the C library
+            self.output.push_str(&format!("    subq ${}, %rsp\n", 16 * count));
+        }
+        self.output.push_str(&std::mem::take(&mut self.code));
+        self.output.push_str(&std::mem::take(&mut self.errors));
+        self.output.push('\n');
+    }
+
+    fn emit_instruction(&mut self, instruction: &Instruction) {
+        let dest = instruction.dest();
+
+        match instruction {
+            Instruction::Const { value, .. } => {
+                let (tag, payload) = match value {
+                    Const::Int(v) => (TAG_INT, format!("movabsq ${}, %rax", v)),
+                    Const::Str(s) => (TAG_STRING, format!("leaq {}(%rip), %rax", self.string(s))),
+                    Const::Void => (TAG_VOID, "xorl %eax, %eax".to_string()),
+                };
+                self.ins(&payload);
+                self.ins(&format!("movq ${}, %rdx", tag));
+            }
+
+            Instruction::Copy { src, .. } => self.load(*src, "%rax", "%rdx"),
+
+            Instruction::Binary { op, left, right, span, .. } => {
+                let checked = self.load_operands(*left, *right);
+                self.emit_binary(op, checked, *span);
+            }
+
+            Instruction::Call { callee: Callee::Builtin(Builtin::Print), args, .. } => {
+                self.load(args[0], "%rdi", "%rsi");
+                self.ins("call nx_print");
+                self.ins(&format!("movq ${}, %rdx", TAG_VOID));
+                self.ins("xorl %eax, %eax");
+            }
+
+            Instruction::Call { callee: Callee::Builtin(builtin), args, span, .. } => {
+                let checked = self.load_operands(args[0], args[1]);
+                self.emit_builtin(*builtin, checked, *span);
+            }
+
+            Instruction::Call { callee: Callee::Function(name), args, span, .. } => {
+                let message = format!("stack overflow: more than {} nested calls", MAX_FRAMES);
+                let overflow = self.error(E_STACK_OVERFLOW, &message, *span);
+                self.ins(&format!("cmpq ${}, nx_depth(%rip)", MAX_FRAMES));
+                self.ins(&format!("jge {}", overflow));
+                self.ins("incq nx_depth(%rip)");
+
+                for arg in args {
+                    let offset = self.offsets[arg.0];
+                    self.ins(&format!("pushq {}(%rbp)", offset + 8));
+                    self.ins(&format!("pushq {}(%rbp)", offset));
+                }
+                self.ins(&format!("call f_{}", name));
+                if !args.is_empty() {
+                    self.ins(&format!("addq ${}, %rsp", 16 * args.len()));
+                }
+                self.ins("decq nx_depth(%rip)");
+            }
+        }
+
+        self.store(dest);
+    }
+
+    /// End a block; `next` is the block that follows it in the function.
+    fn emit_terminator(&mut self, terminator: &Terminator, next: BlockId) {
+        match *terminator {
+            Terminator::Jump(target) => {
+                if target != next {
+                    let label = self.block_label(target);
+                    self.ins(&format!("jmp {}", label));
+                }
+            }
+
+            Terminator::Branch { condition, if_true, if_false } => {
+                self.load(condition, "%rax", "%rdx");
+                let tagged = self.types[condition.0] != Type::Int;
+                let label = self.block_label(if_false);
+                self.jump_if_false(&label, tagged);
+                if if_true != next {
+                    let label = self.block_label(if_true);
+                    self.ins(&format!("jmp {}", label));
+                }
+            }
+
+            Terminator::Return(value) => {
+                self.load(value, "%rax", "%rdx");
+                self.ins("leave");
+                self.ins("ret");
+            }
+        }
+    }
+
+    /// Load the operands of an operator into `%rax`/`%rdx` and
+    /// `%rcx`/`%r8`. Returns whether they need checking to be ints.
+    fn load_operands(&mut self, left: Reg, right: Reg) -> bool {
+        self.load(left, "%rax", "%rdx");
+        self.load(right, "%rcx", "%r8");
+        self.types[left.0] != Type::Int || self.types[right.0] != Type::Int
+    }
+
+    fn emit_binary(&mut self, op: &BinOp, checked: bool, span: Span) {
+        let symbol = op.symbol();
+        self.check_ints(symbol, checked, span);
+
+        match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                let instruction = match op {
+                    BinOp::Add => "addq",
+                    BinOp::Sub => "subq",
+                    _ => "imulq",
+                };
+                // Into a scratch register, so the error can show both operands
+                self.ins("movq %rax, %r9");
+                self.ins(&format!("{} %rcx, %r9", instruction));
+                let overflow = self.overflow_error(symbol, span);
+                self.ins(&format!("jo {}", overflow));
+                self.ins("movq %r9, %rax");
+            }
+
+            BinOp::Div => {
+                let zero = self.error(E_DIVISION_BY_ZERO, "attempt to divide by zero", span);
+                self.ins("testq %rcx, %rcx");
+                self.ins(&format!("jz {}", zero));
+
+                // `idiv` traps on the one quotient that does not fit
+                let divide = self.label();
+                let overflow = self.overflow_error(symbol, span);
+                self.ins("cmpq $-1, %rcx");
+                self.ins(&format!("jne {}", divide));
+                self.ins(&format!("movabsq ${}, %r9", i64::MIN));
+                self.ins("cmpq %r9, %rax");
+                self.ins(&format!("je {}", overflow));
+                self.place(&divide);
+
+                self.ins("cqto");
+                self.ins("idivq %rcx");
+                self.ins(&format!("movq ${}, %rdx", TAG_INT));
+            }
+
+            BinOp::Greater | BinOp::Less | BinOp::Equal => {
+                let set = match op {
+                    BinOp::Greater => "setg",
+                    BinOp::Less => "setl",
+                    _ => "sete",
+                };
+                self.ins("cmpq %rcx, %rax");
+                self.ins(&format!("{} %al", set));
+                self.ins("movzbl %al, %eax");
+            }
+        }
+    }
+
+    fn emit_builtin(&mut self, builtin: Builtin, checked: bool, span: Span) {
+        self.check_ints(builtin.name(), checked, span);
+
+        let instruction = match builtin {
+            Builtin::WrappingAdd | Builtin::SaturatingAdd => "addq",
+            Builtin::WrappingSub | Builtin::SaturatingSub => "subq",
+            Builtin::WrappingMul | Builtin::SaturatingMul => "imulq",
+            Builtin::Print => unreachable!("`print` is not arithmetic"),
+        };
+
+        if matches!(builtin, Builtin::WrappingAdd | Builtin::WrappingSub | Builtin::WrappingMul) {
+            self.ins(&format!("{} %rcx, %rax", instruction));
+            return;
+        }
+
+        // On overflow the result is clamped to one bound or the other,
+        // depending on the sign of a register
+        let (sign, if_positive, if_negative) = match builtin {
+            Builtin::SaturatingAdd => ("%rcx", i64::MAX, i64::MIN),
+            Builtin::SaturatingSub => ("%rcx", i64::MIN, i64::MAX),
+            _ => ("%r10", i64::MAX, i64::MIN),
+        };
+
+        let done = self.label();
+        if builtin == Builtin::SaturatingMul {
+            // Negative when the operands' signs differ
+            self.ins("movq %rax, %r10");
+            self.ins("xorq %rcx, %r10");
+        }
+        self.ins("movq %rax, %r9");
+        self.ins(&format!("{} %rcx, %r9", instruction));
+        self.ins(&format!("jno {}", done));
+        self.ins(&format!("movabsq ${}, %r9", if_positive));
+        self.ins(&format!("testq {}, {}", sign, sign));
+        self.ins(&format!("jns {}", done));
+        self.ins(&format!("movabsq ${}, %r9", if_negative));
+        self.place(&done);
+        self.ins("movq %r9, %rax");
+    }
+
+    /// Branch to an error unless the operands in `%rdx` and `%r8` are ints,
+    /// if their types leave it in doubt (`checked`).
+    fn check_ints(&mut self, op: &str, checked: bool, span: Span) {
+        if !checked {
+            return;
+        }
+        let label = self.label();
+        let op = self.string(op);
+        self.ins("movq %rdx, %r9");
+        self.ins("orq %r8, %r9");
+        self.ins(&format!("jnz {}", label));
+
+        self.errors.push_str(&format!("{}:\n", label));
+        for instruction in [
+            format!("leaq {}(%rip), %rdi", op),
+            "movq %rdx, %rsi".to_string(),
+            "movq %r8, %rdx".to_string(),
+        ] {
+            self.errors.push_str(&format!("    {}\n", instruction));
+        }
+        self.error_location(span, "%ecx", "%r8d");
+        self.errors.push_str("    call nx_type_error\n");
+    }
+
+    /// An error path reporting that `%rax op %rcx` overflows.
+    fn overflow_error(&mut self, op: &str, span: Span) -> String {
+        let label = self.label();
+        let op = self.string(op);
+        self.errors.push_str(&format!("{}:\n", label));
+        self.errors.push_str("    movq %rax, %rdi\n");
+        self.errors.push_str(&format!("    leaq {}(%rip), %rsi\n", op));
+        self.errors.push_str("    movq %rcx, %rdx\n");
+        self.error_location(span, "%ecx", "%r8d");
+        self.errors.push_str("    call nx_overflow_error\n");
+        label
+    }
+
+    /// An error path reporting `message` with error `code`.
+    fn error(&mut self, code: &str, message: &str, span: Span) -> String {
+        let label = self.label();
+        let (code, message) = (self.string(code), self.string(message));
+        self.errors.push_str(&format!("{}:\n", label));
+        self.errors.push_str(&format!("    leaq {}(%rip), %rdi\n", code));
+        self.errors.push_str(&format!("    leaq {}(%rip), %rsi\n", message));
+        self.error_location(span, "%edx", "%ecx");
+        self.errors.push_str("    call nx_error\n");
+        label
+    }
+
+    fn error_location(&mut self, span: Span, line: &str, column: &str) {
+        self.errors.push_str(&format!("    movl ${}, {}\n", span.line, line));
+        self.errors.push_str(&format!("    movl ${}, {}\n", span.column, column));
+    }
+
+    /// Jump unless `%rax`/`%rdx` holds a true value. Only nonzero ints are
+    /// true; the tag needs no test if the value is known to be an int.
+    fn jump_if_false(&mut self, target: &str, tagged: bool) {
+        if tagged {
+            self.ins("testq %rdx, %rdx");
+            self.ins(&format!("jnz {}", target));
+        }
+        self.ins("testq %rax, %rax");
+        self.ins(&format!("jz {}", target));
+    }
+
+    fn load(&mut self, reg: Reg, payload: &str, tag: &str) {
+        let offset = self.offsets[reg.0];
+        self.ins(&format!("movq {}(%rbp), {}", offset + 8, payload));
+        self.ins(&format!("movq {}(%rbp), {}", offset, tag));
+    }
+
+    fn store(&mut self, reg: Reg) {
+        let offset = self.offsets[reg.0];
+        self.ins(&format!("movq %rax, {}(%rbp)", offset + 8));
+        self.ins(&format!("movq %rdx, {}(%rbp)", offset));
+    }
+
+    fn block_label(&self, block: BlockId) -> String {
+        format!(".Lf{}b{}", self.function, block.0)
+    }
+
+    /// Label of a read-only copy of `value`.
+    fn string(&mut self, value: &str) -> String {
+        let next = self.strings.len();
+        self.strings.entry(value.to_string()).or_insert_with(|| format!(".Ls{}", next)).clone()
+    }
+
+    fn label(&mut self) -> String {
+        self.next_label += 1;
+        format!(".L{}", self.next_label)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.code.push_str(&format!("{}:\n", label));
+    }
+
+    fn ins(&mut self, instruction: &str) {
+        self.code.push_str("    ");
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+}
+
+/// Give every register that is not a parameter a stack slot, sharing
+/// slots between registers that are never live at the same time (greedy
+/// colouring of the interference graph). Returns each register's slot and
+/// the number of slots.
+fn assign_slots(func: &Function) -> (Vec<usize>, usize) {
+    let count = func.register_count();
+    let mut interferes: Vec<HashSet<usize>> = vec![HashSet::new(); count];
+
+    let live_in = func.live_in();
+    for block in &func.blocks {
+        let mut live: HashSet<Reg> = block
+            .terminator
+            .successors()
+            .iter()
+            .flat_map(|s| live_in[s.0].iter().copied())
+            .collect();
+        live.extend(block.terminator.operand());
+
+        for instruction in block.instructions.iter().rev() {
+            // The operands are read before the result is written, so the
+            // result can take the slot of one that dies here
+            let dest = instruction.dest();
+            live.remove(&dest);
+            for other in &live {
+                interferes[dest.0].insert(other.0);
+                interferes[other.0].insert(dest.0);
+            }
+            live.extend(instruction.operands());
+        }
+    }
+
+    let mut slots = vec![usize::MAX; count];
+    let mut used = 0;
+    for r in (0..count).filter(|&r| !func.params.contains(&Reg(r))) {
+        let taken: HashSet<usize> = interferes[r].iter().map(|&other| slots[other]).collect();
+        let slot = (0..).find(|slot| !taken.contains(slot)).expect("slots are unbounded");
+        slots[r] = slot;
+        used = used.max(slot + 1);
+    }
+    (slots, used)
+}
+
+/// `s` as a string for the `.string` directive.
+fn asm_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,109 @@
+//! Integer semantics on every engine, with and without `-O` (which must
+//! not fold an operation that fails): checked `+ - * /` that report
+//! overflow and division by zero where they happen, and the wrapping and
+//! saturating builtins for when wraparound is intended.
+
+use std::path::{Path, PathBuf};
+
+mod common;
+
+use common::{CONFIGURATIONS, command, write_file};
+
+const MAX: &str = "9223372036854775807";
+/// There are no negative literals.
+const MIN: &str = "(0 - 9223372036854775807 - 1)";
+
+fn write_program(name: &str, source: &str) -> PathBuf {
+    write_file("arithmetic", &format!("{}.nx", name), source)
+}
+
+/// Run `program` in every configuration and return its stdout, checking
+/// they all agree on it, on stderr and on the exit code.
+fn run(program: &Path, expected_code: i32) -> (String, String) {
+    let mut first: Option<(String, String)> = None;
+
+    for flags in CONFIGURATIONS {
+        let output = command().args(flags).arg(program).output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+        assert_eq!(output.status.code(), Some(expected_code), "{} {:?}: {}", program.display(), flags, stderr);
+        match &first {
+            None => first = Some((stdout, stderr)),
+            Some(expected) => {
+                assert_eq!(&(stdout, stderr), expected, "{} {:?}", program.display(), flags)
+            }
+        }
+    }
+
+    first.unwrap()
+}
+
+#[test]
+fn failing_operations_report_an_error_at_the_operator() {
+    let cases = [
+        (MAX, "+", "1", "E0104", "attempt to compute `9223372036854775807 + 1`, which overflows"),
+        (MIN, "-", "1", "E0104", "attempt to compute `-9223372036854775808 - 1`, which overflows"),
+        (MAX, "*", "2", "E0104", "attempt to compute `9223372036854775807 * 2`, which overflows"),
+        (MIN, "/", "(0 - 1)", "E0104", "attempt to compute `-9223372036854775808 / -1`, which overflows"),
+        ("1", "/", "0", "E0103", "attempt to divide by zero"),
+    ];
+
+    for (i, (a, op, b, code, message)) in cases.into_iter().enumerate() {
+        // Once on constants, once on values only known at run time
+        let sources = [
+            format!("fn main() {{\n    print(1);\n    print({} {} {});\n}}\n", a, op, b),
+            format!(
+                "fn apply(a, b) {{\n    return a {} b;\n}}\n\nfn main() {{\n    print(1);\n    print(apply({}, {}));\n}}\n",
+                op, a, b
+            ),
+        ];
+
+        for (j, source) in sources.iter().enumerate() {
+            let program = write_program(&format!("fails_{}_{}", i, j), source);
+            let (stdout, stderr) = run(&program, 1);
+
+            // Output before the error is kept
+            assert_eq!(stdout, "1\n", "{}", source);
+            let mut lines = stderr.lines();
+            assert_eq!(lines.next(), Some(format!("error[{}]: {}", code, message).as_str()), "{}", source);
+
+            let column = if j == 0 { 11 } else { 12 };
+            let line = if j == 0 { 3 } else { 2 };
+            let location = format!("--> {}:{}:{}", program.display(), line, column);
+            assert_eq!(lines.next().map(str::trim_start), Some(location.as_str()), "{}", source);
+        }
+    }
+}
+
+#[test]
+fn operations_at_the_bounds() {
+    let cases = [
+        (format!("{} - 1 + 1", MAX), MAX),
+        (format!("{} + 1 - 1", MIN), "-9223372036854775808"),
+        ("7 / 2".to_string(), "3"),
+        ("(0 - 7) / 2".to_string(), "-3"),
+        (format!("{} / (0 - 1)", MAX), "-9223372036854775807"),
+        (format!("wrapping_add({}, 1)", MAX), "-9223372036854775808"),
+        (format!("wrapping_sub({}, 1)", MIN), MAX),
+        (format!("wrapping_mul({}, 2)", MAX), "-2"),
+        (format!("saturating_add({}, 1)", MAX), MAX),
+        (format!("saturating_sub({}, 1)", MIN), "-9223372036854775808"),
+        (format!("saturating_mul({}, 2)", MIN), "-9223372036854775808"),
+        (format!("saturating_mul({}, 0 - 1)", MIN), MAX),
+        ("wrapping_add(2, 3) + saturating_sub(10, 4)".to_string(), "11"),
+    ];
+
+    let mut source = "fn main() {\n".to_string();
+    let mut expected = String::new();
+    for (expr, value) in &cases {
+        source.push_str(&format!("    print({});\n", expr));
+        expected.push_str(&format!("{}\n", value));
+    }
+    source.push_str("}\n");
+
+    let program = write_program("bounds", &source);
+    let (stdout, stderr) = run(&program, 0);
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, expected);
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the integration tests. Each test file is its own
+//! crate and uses only some of them.
+
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Every way to run a source file, which must all behave the same.
+pub const CONFIGURATIONS: [&[&str]; 4] = [
+    &["--engine=vm"],
+    &["--engine=vm", "-O"],
+    &["--engine=tree"],
+    &["--engine=tree", "-O"],
+];
+
+pub fn command() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_compiler"))
+}
+
+pub fn nexo<S: AsRef<OsStr>>(args: &[S]) -> Output {
+    command().args(args).output().unwrap()
+}
+
+/// Run nexo from inside `dir`, so file names in its output are stable.
+pub fn nexo_in<S: AsRef<OsStr>>(dir: &Path, args: &[S]) -> Output {
+    command().args(args).current_dir(dir).output().unwrap()
+}
+
+/// The `.nx` files in `dir`, sorted.
+pub fn cases(dir: &Path) -> Vec<PathBuf> {
+    let mut cases: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nx"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no programs in {}", dir.display());
+    cases
+}
+
+/// Every program in `examples/` and `tests/corpus/`.
+pub fn programs() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = cases(&root.join("../examples"));
+    programs.extend(cases(&root.join("tests/corpus")));
+    programs.sort();
+    programs
+}
+
+/// A directory for one test file's outputs, created if needed.
+pub fn out_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write `contents` to `file` in `out_dir(dir)` and return its path.
+pub fn write_file(dir: &str, file: &str, contents: &str) -> PathBuf {
+    let path = out_dir(dir).join(file);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+pub fn have_cc() -> bool {
+    Command::new("cc").arg("--version").output().is_ok_and(|o| o.status.success())
+}
+
+/// The backends that can build executables here, none without `cc`.
+pub fn native_backends() -> &'static [&'static str] {
+    if !have_cc() {
+        &[]
+    } else if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        &["--backend=c", "--backend=asm"]
+    } else {
+        &["--backend=c"]
+    }
+}
+
+/// The first error line and its location, with the location's indentation
+/// (which depends on the width of line numbers) removed: executables print
+/// no snippet and no stack trace.
+pub fn error_summary(stderr: &str) -> Vec<&str> {
+    let lines: Vec<&str> = stderr.lines().collect();
+
+    match lines.iter().position(|l| l.starts_with("error[")) {
+        Some(i) => lines[i..].iter().take(2).map(|l| l.trim_start()).collect(),
+        None => Vec::new(),
+    }
+}
@@ -0,0 +1,104 @@
+//! Differential tests for the two engines. Every program in `examples/`
+//! and `tests/corpus/` runs on the VM and on the tree interpreter, with and
+//! without `-O`, and all four runs must print the same stdout and stderr
+//! and exit with the same code.
+//!
+//! Corpus programs also pin down the expected behavior: `foo.nx` must
+//! print `foo.stdout` and exit with the code in `foo.exit` (0 if there is
+//! no such file).
+
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{CONFIGURATIONS, cases, command};
+
+struct Run {
+    stdout: String,
+    stderr: String,
+    code: Option<i32>,
+}
+
+fn run(program: &Path, flags: &[&str]) -> Run {
+    let output = command().args(flags).arg(program).output().unwrap();
+
+    Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code(),
+    }
+}
+
+/// Run `program` in every configuration and describe each way the runs
+/// differ from the first one.
+fn check_engines_agree(program: &Path, failures: &mut Vec<String>) -> Run {
+    let mut runs = CONFIGURATIONS.iter().map(|flags| (flags, run(program, flags)));
+    let (base_flags, base) = runs.next().unwrap();
+
+    for (flags, other) in runs {
+        if other.stdout != base.stdout || other.stderr != base.stderr || other.code != base.code {
+            failures.push(format!(
+                "{}: {:?} and {:?} disagree\n--- {:?} (exit {:?})\n{}{}--- {:?} (exit {:?})\n{}{}",
+                program.display(),
+                base_flags,
+                flags,
+                base_flags,
+                base.code,
+                base.stdout,
+                base.stderr,
+                flags,
+                other.code,
+                other.stdout,
+                other.stderr
+            ));
+        }
+    }
+
+    base
+}
+
+#[test]
+fn examples() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+    let mut failures = Vec::new();
+
+    for program in cases(&dir) {
+        check_engines_agree(&program, &mut failures);
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut failures = Vec::new();
+
+    for program in cases(&dir) {
+        let expected_stdout = fs::read_to_string(program.with_extension("stdout"))
+            .unwrap_or_else(|e| panic!("{}: missing .stdout file: {}", program.display(), e));
+        let expected_code = match fs::read_to_string(program.with_extension("exit")) {
+            Ok(text) => text.trim().parse().unwrap_or_else(|e| {
+                panic!("{}: bad .exit file: {}", program.display(), e)
+            }),
+            Err(_) => 0,
+        };
+
+        let run = check_engines_agree(&program, &mut failures);
+
+        if run.stdout != expected_stdout || run.code != Some(expected_code) {
+            failures.push(format!(
+                "{} (exit {:?}, expected {})\n--- expected\n{}--- got\n{}{}",
+                program.display(),
+                run.code,
+                expected_code,
+                expected_stdout,
+                run.stdout,
+                run.stderr
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
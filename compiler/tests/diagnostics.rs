@@ -0,0 +1,129 @@
+//! Golden tests for diagnostics. Every `tests/diagnostics/foo.nx` is run
+//! in both error formats, from inside that directory so file names in the
+//! output are stable: its stderr must match `foo.stderr` as rendered for
+//! humans and `foo.json` as JSON lines, and it must exit with code 1.
+//! The tree engine must print the same as the VM, runtime errors included.
+//!
+//! Run with `NEXO_BLESS=1` to write the expected files from the current
+//! output instead, then review the diff.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{cases, nexo_in, out_dir};
+
+#[test]
+fn diagnostics_match_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/diagnostics");
+    let bless = env::var_os("NEXO_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for case in cases(&dir) {
+        let name = case.file_name().unwrap().to_str().unwrap();
+
+        let runs = [
+            ("--error-format=human", "stderr"),
+            ("--error-format=json", "json"),
+            ("--engine=tree", "stderr"),
+        ];
+        for (flag, extension) in runs {
+            let output = nexo_in(&dir, &[flag, name]);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let golden = case.with_extension(extension);
+
+            if bless {
+                if flag != "--engine=tree" {
+                    fs::write(&golden, stderr.as_bytes()).unwrap();
+                }
+                continue;
+            }
+
+            assert_eq!(output.status.code(), Some(1), "{} ({}): exit code", name, flag);
+            let expected = fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("{}: {}", golden.display(), e));
+            if stderr != expected {
+                failures.push(format!(
+                    "{} ({}):\n--- expected\n{}--- got\n{}",
+                    name, flag, expected, stderr
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn explain_prints_the_long_description() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/diagnostics");
+    let expected = fs::read_to_string(dir.join("explain_E0003.stdout")).unwrap();
+
+    // Codes are matched case-insensitively
+    for code in ["E0003", "e0003"] {
+        let output = nexo_in(&dir, &["--explain", code]);
+        assert!(output.status.success(), "--explain {}", code);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "--explain {}", code);
+    }
+
+    let codes = (1..=12).map(|n| format!("E{:04}", n)).chain((100..=104).map(|n| format!("E{:04}", n)));
+    for code in codes {
+        let output = nexo_in(&dir, &["--explain", &code]);
+        assert!(output.status.success(), "{} has no explanation", code);
+    }
+
+    let output = nexo_in(&dir, &["--explain", "E9999"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: no extended information for `E9999`\n"
+    );
+}
+
+/// The error line and the stack trace of a rendered runtime error, without
+/// the snippet (bytecode files do not keep their source).
+fn error_and_trace(stderr: &str) -> Vec<&str> {
+    stderr
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("error[") || l.starts_with("= note: at "))
+        .collect()
+}
+
+#[test]
+fn bytecode_files_keep_runtime_error_locations() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/diagnostics");
+    let out_dir = out_dir("diagnostics");
+
+    let runtime = cases(&dir).into_iter().filter(|case| {
+        case.file_name().unwrap().to_str().unwrap().starts_with("runtime_")
+    });
+    for case in runtime {
+        let name = case.file_name().unwrap().to_str().unwrap();
+        let golden = fs::read_to_string(case.with_extension("stderr")).unwrap();
+        let expected = error_and_trace(&golden);
+        assert!(!expected.is_empty(), "{}: no runtime error", name);
+
+        for optimize in [None, Some("-O")] {
+            let nxc = out_dir.join(case.with_extension("nxc").file_name().unwrap());
+            let nxs = out_dir.join(case.with_extension("nxs").file_name().unwrap());
+            let (nxc, nxs) = (nxc.to_str().unwrap(), nxs.to_str().unwrap());
+
+            let mut build = vec!["build", name, "-o", nxc];
+            build.extend(optimize);
+            assert!(nexo_in(&dir, &build).status.success(), "{}: build", name);
+            let disasm = nexo_in(&dir, &["disasm", nxc]);
+            assert!(disasm.status.success(), "{}: disasm", name);
+            fs::write(nxs, &disasm.stdout).unwrap();
+
+            for file in [nxc, nxs] {
+                let output = nexo_in(&dir, &["run", file]);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                assert_eq!(output.status.code(), Some(1), "{}", file);
+                assert_eq!(error_and_trace(&stderr), expected, "{} {:?}", file, optimize);
+            }
+        }
+    }
+}
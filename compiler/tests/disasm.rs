@@ -0,0 +1,137 @@
+//! `nexo disasm` output must assemble back to the same program: for every
+//! example and corpus program, disassembling the assembly gives the same
+//! text, and running it gives the same output as running the source.
+//!
+//! Every `tests/disasm/foo.nx` is also disassembled from inside that
+//! directory, and must match `foo.nxs`, or `foo.O1.nxs` with the peephole
+//! pass. Run with `NEXO_BLESS=1` to write those from the current output.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{cases, nexo, nexo_in, out_dir, programs};
+
+/// Flags for each golden file, by extension.
+const LEVELS: [(&str, &str); 2] = [("-O0", "nxs"), ("-O1", "O1.nxs")];
+
+#[test]
+fn disassembly_round_trips() {
+    let out_dir = out_dir("disasm");
+    let mut checked = 0;
+
+    for program in programs() {
+        let disasm = nexo(&[Path::new("disasm"), &program]);
+        if !disasm.status.success() {
+            // Programs with compile errors, like `bad.nx`
+            continue;
+        }
+        let text = String::from_utf8(disasm.stdout).unwrap();
+
+        let asm_file = out_dir.join(program.file_name().unwrap()).with_extension("nxs");
+        fs::write(&asm_file, &text).unwrap();
+
+        let again = nexo(&[Path::new("disasm"), &asm_file]);
+        assert_eq!(
+            String::from_utf8_lossy(&again.stdout),
+            text,
+            "{}: disassembly changed after assembling it",
+            program.display()
+        );
+
+        let from_source = nexo(&[Path::new("run"), &program]);
+        let from_asm = nexo(&[Path::new("run"), &asm_file]);
+        assert_eq!(
+            String::from_utf8_lossy(&from_asm.stdout),
+            String::from_utf8_lossy(&from_source.stdout),
+            "{}: assembled program behaves differently",
+            program.display()
+        );
+        assert_eq!(from_asm.status.code(), from_source.status.code());
+
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
+
+#[test]
+fn disassembly_matches_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/disasm");
+    let bless = env::var_os("NEXO_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for case in cases(&dir) {
+        let name = case.file_name().unwrap().to_str().unwrap();
+
+        for (flag, extension) in LEVELS {
+            let disasm = nexo_in(&dir, &["disasm", flag, name]);
+            assert!(disasm.status.success(), "{} {}: {}", name, flag, String::from_utf8_lossy(&disasm.stderr));
+
+            let text = String::from_utf8(disasm.stdout).unwrap();
+            let golden = case.with_extension(extension);
+            if bless {
+                fs::write(&golden, &text).unwrap();
+                continue;
+            }
+
+            let expected = fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("{}: {}", golden.display(), e));
+            if text != expected {
+                failures.push(format!("{} ({}):\n--- expected\n{}--- got\n{}", name, flag, expected, text));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// With `-O1`, no jump lands on another jump or on the instruction right
+/// after it: the peephole pass threads the first and drops the second.
+#[test]
+fn optimized_jumps_go_straight_to_their_target() {
+    let mut checked = 0;
+
+    for program in programs() {
+        let disasm = nexo(&[Path::new("disasm"), Path::new("-O1"), &program]);
+        if !disasm.status.success() {
+            continue;
+        }
+        let text = String::from_utf8(disasm.stdout).unwrap();
+
+        // Instructions only, with the label each one is reached by
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('.') && !line.starts_with("fn "))
+            .collect();
+        let first_after = |label: &str| {
+            let at = lines.iter().position(|line| *line == format!("{}:", label)).unwrap();
+            lines[at + 1..].iter().find(|line| !line.ends_with(':')).copied()
+        };
+
+        for (i, line) in lines.iter().enumerate() {
+            let Some(label) = line.strip_prefix("jump ").or_else(|| line.strip_prefix("jump_if_false ")) else {
+                continue;
+            };
+            assert!(
+                !first_after(label).is_some_and(|next| next.starts_with("jump ")),
+                "{}: `{}` lands on a jump",
+                program.display(),
+                line
+            );
+            assert_ne!(
+                lines.get(i + 1),
+                Some(&format!("{}:", label).as_str()),
+                "{}: `{}` jumps to the next instruction",
+                program.display(),
+                line
+            );
+        }
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
@@ -0,0 +1,689 @@
+//! Random programs through the whole pipeline. Each one is generated from
+//! the grammar (functions, lets, nested `if`/`while`, `break`/`continue`,
+//! calls, arithmetic), is well-formed by construction and always
+//! terminates, so every run must be accepted by the front end, must not
+//! panic, and must behave the same on the VM and the tree interpreter,
+//! with and without `-O`. When a C compiler (`cc`) is available the first
+//! cases are also built as native executables with every backend at every
+//! optimization level, which must behave like the VM. Failing programs are
+//! shrunk before reporting.
+//!
+//! The seed is fixed; set `NEXO_FUZZ_SEED`, `NEXO_FUZZ_CASES` and
+//! `NEXO_FUZZ_NATIVE_CASES` to explore further.
+
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::{CONFIGURATIONS, command, error_summary, native_backends, out_dir};
+
+const DEFAULT_SEED: u64 = 0x6e65_786f;
+const DEFAULT_CASES: usize = 150;
+/// Building an executable takes a C compiler run, so fewer cases do.
+const DEFAULT_NATIVE_CASES: usize = 20;
+
+const LEVELS: [&str; 3] = ["-O0", "-O1", "-O2"];
+
+/// Generated programs finish in milliseconds; anything slower is stuck.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* ===========================
+   Programs
+=========================== */
+
+#[derive(Clone)]
+struct Program {
+    functions: Vec<Function>,
+}
+
+#[derive(Clone)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Clone)]
+enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr),
+    Print(Expr),
+    Return(Expr),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    /// `let counter = 0; while (counter < limit) { counter = counter + 1; ... }`,
+    /// so that every loop ends.
+    While { counter: String, limit: i64, body: Vec<Stmt> },
+    Break,
+    Continue,
+}
+
+#[derive(Clone)]
+enum Expr {
+    Int(i64),
+    Str(String),
+    Var(String),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "fn {}({}) {{", func.name, func.params.join(", "))?;
+            write_block(f, &func.body, 1)?;
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter, block: &[Stmt], depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+
+    for stmt in block {
+        match stmt {
+            Stmt::Let(name, value) => writeln!(f, "{}let {} = {};", indent, name, value)?,
+            Stmt::Assign(name, value) => writeln!(f, "{}{} = {};", indent, name, value)?,
+            Stmt::Print(value) => writeln!(f, "{}print({});", indent, value)?,
+            Stmt::Return(value) => writeln!(f, "{}return {};", indent, value)?,
+            Stmt::Break => writeln!(f, "{}break;", indent)?,
+            Stmt::Continue => writeln!(f, "{}continue;", indent)?,
+
+            Stmt::If(condition, then_block, else_block) => {
+                writeln!(f, "{}if ({}) {{", indent, condition)?;
+                write_block(f, then_block, depth + 1)?;
+                if let Some(else_block) = else_block {
+                    writeln!(f, "{}}} else {{", indent)?;
+                    write_block(f, else_block, depth + 1)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            }
+
+            Stmt::While { counter, limit, body } => {
+                writeln!(f, "{}let {} = 0;", indent, counter)?;
+                writeln!(f, "{}while ({} < {}) {{", indent, counter, limit)?;
+                writeln!(f, "{}    {} = {} + 1;", indent, counter, counter)?;
+                write_block(f, body, depth + 1)?;
+                writeln!(f, "{}}}", indent)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Int(v) => write!(f, "{}", v),
+            Expr::Str(s) => write!(f, "\"{}\"", s),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Binary(left, op, right) => write!(f, "({} {} {})", left, op, right),
+            Expr::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/* ===========================
+   Generator
+=========================== */
+
+/// xorshift64*, so runs are reproducible without extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const MAX_NESTING: usize = 3;
+const MAX_LOOP_NESTING: usize = 2;
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    /// Functions a call may target, with their arity: only the ones defined
+    /// earlier, so there is no recursion.
+    callable: Vec<(String, usize)>,
+    /// Variables in scope, innermost scope last. Loop counters are visible
+    /// but never assigned.
+    scopes: Vec<Vec<(String, bool)>>,
+    next_var: usize,
+    loops: usize,
+}
+
+fn generate(rng: &mut Rng) -> Program {
+    let count = 1 + rng.below(4);
+    let mut functions: Vec<Function> = Vec::new();
+
+    for i in 0..=count {
+        let is_main = i == count;
+        let params: Vec<String> = if is_main {
+            Vec::new()
+        } else {
+            (0..rng.below(3)).map(|p| format!("p{}", p)).collect()
+        };
+
+        let mut generator = Generator {
+            rng: &mut *rng,
+            callable: functions.iter().map(|f| (f.name.clone(), f.params.len())).collect(),
+            scopes: vec![params.iter().map(|p| (p.clone(), true)).collect()],
+            next_var: 0,
+            loops: 0,
+        };
+
+        let mut body = generator.block(0);
+        if !is_main && generator.rng.chance(80) {
+            body.push(Stmt::Return(generator.expr(2)));
+        }
+
+        functions.push(Function {
+            name: if is_main { "main".to_string() } else { format!("f{}", i) },
+            params,
+            body,
+        });
+    }
+
+    Program { functions }
+}
+
+impl Generator<'_> {
+    fn block(&mut self, nesting: usize) -> Vec<Stmt> {
+        self.scopes.push(Vec::new());
+        let len = 1 + self.rng.below(if nesting == 0 { 6 } else { 4 });
+        let block = (0..len).map(|_| self.stmt(nesting)).collect();
+        self.scopes.pop();
+        block
+    }
+
+    fn stmt(&mut self, nesting: usize) -> Stmt {
+        loop {
+            match self.rng.below(10) {
+                0 | 1 => {
+                    // Sometimes shadow a variable from an outer scope
+                    let name = match self.visible().last() {
+                        Some((name, _)) if self.rng.chance(20) => name.clone(),
+                        _ => self.fresh_var(),
+                    };
+                    let value = self.expr(3);
+                    self.declare(&name, true);
+                    return Stmt::Let(name, value);
+                }
+
+                2 => {
+                    let assignable: Vec<String> = self.visible()
+                        .into_iter()
+                        .filter(|(_, assignable)| *assignable)
+                        .map(|(name, _)| name)
+                        .collect();
+                    if assignable.is_empty() {
+                        continue;
+                    }
+                    let name = self.rng.pick(&assignable).clone();
+                    return Stmt::Assign(name, self.expr(3));
+                }
+
+                3 | 4 => {
+                    let value = if self.rng.chance(5) {
+                        Expr::Str(self.rng.pick(&["hello", "nexo", ""]).to_string())
+                    } else {
+                        self.expr(3)
+                    };
+                    return Stmt::Print(value);
+                }
+
+                5 if nesting < MAX_NESTING => {
+                    let condition = self.expr(2);
+                    let then_block = self.block(nesting + 1);
+                    let else_block = self.rng.chance(50).then(|| self.block(nesting + 1));
+                    return Stmt::If(condition, then_block, else_block);
+                }
+
+                6 if nesting < MAX_NESTING && self.loops < MAX_LOOP_NESTING => {
+                    let counter = self.fresh_var();
+                    let limit = self.rng.below(4) as i64;
+                    self.declare(&counter, false);
+
+                    self.loops += 1;
+                    let body = self.block(nesting + 1);
+                    self.loops -= 1;
+
+                    return Stmt::While { counter, limit, body };
+                }
+
+                7 if self.loops > 0 => {
+                    return if self.rng.chance(50) { Stmt::Break } else { Stmt::Continue };
+                }
+
+                8 if nesting > 0 && self.rng.chance(30) => return Stmt::Return(self.expr(2)),
+
+                _ => {}
+            }
+        }
+    }
+
+    fn expr(&mut self, depth: usize) -> Expr {
+        if depth == 0 || self.rng.chance(30) {
+            return self.leaf();
+        }
+
+        if self.rng.chance(75) {
+            let op = *self.rng.pick(&["+", "-", "*", "/", "<", ">", "=="]);
+            let left = self.expr(depth - 1);
+            let right = self.expr(depth - 1);
+            return Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+
+        let builtins = ["wrapping_add", "wrapping_mul", "saturating_sub", "saturating_mul"];
+        let (name, arity) = if !self.callable.is_empty() && self.rng.chance(70) {
+            self.rng.pick(&self.callable).clone()
+        } else {
+            (self.rng.pick(&builtins).to_string(), 2)
+        };
+        let args = (0..arity).map(|_| self.expr(depth - 1)).collect();
+        Expr::Call(name, args)
+    }
+
+    fn leaf(&mut self) -> Expr {
+        let visible = self.visible();
+        if !visible.is_empty() && self.rng.chance(60) {
+            return Expr::Var(self.rng.pick(&visible).0.clone());
+        }
+
+        if self.rng.chance(1) {
+            return Expr::Str("s".to_string());
+        }
+
+        // Mostly small numbers, sometimes ones close to overflowing
+        Expr::Int(match self.rng.below(20) {
+            0 => i64::MAX,
+            1 => 1 << 62,
+            2 => 3_037_000_500,
+            _ => self.rng.below(10) as i64,
+        })
+    }
+
+    fn fresh_var(&mut self) -> String {
+        self.next_var += 1;
+        format!("v{}", self.next_var)
+    }
+
+    fn declare(&mut self, name: &str, assignable: bool) {
+        self.scopes.last_mut().unwrap().push((name.to_string(), assignable));
+    }
+
+    /// Every visible variable, as the innermost declaration of its name.
+    fn visible(&self) -> Vec<(String, bool)> {
+        let mut visible: Vec<(String, bool)> = Vec::new();
+        for (name, assignable) in self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()) {
+            if !visible.iter().any(|(seen, _)| seen == name) {
+                visible.push((name.clone(), *assignable));
+            }
+        }
+        visible
+    }
+}
+
+/* ===========================
+   Running
+=========================== */
+
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// The front end rejected a well-formed program, or building it as an
+    /// executable failed.
+    Rejected,
+    Panic,
+    Timeout,
+    /// Two configurations behaved differently.
+    Mismatch,
+}
+
+struct Outcome {
+    failure: Failure,
+    details: String,
+}
+
+struct Run {
+    stdout: String,
+    stderr: String,
+    code: Option<i32>,
+}
+
+fn run(command: &mut Command) -> Option<Run> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > TIMEOUT {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let output = child.wait_with_output().unwrap();
+    Some(Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code(),
+    })
+}
+
+fn mismatch(runs: [(&[&str], &Run); 2]) -> Outcome {
+    let mut details = String::new();
+    for (flags, run) in runs {
+        let _ = write!(
+            details,
+            "--- {:?} (exit {:?})\n{}{}",
+            flags, run.code, run.stdout, run.stderr
+        );
+    }
+    Outcome { failure: Failure::Mismatch, details }
+}
+
+/// Run `program` in every configuration, then as an executable from each of
+/// `backends`, and report the first problem.
+fn check(program: &Program, file: &Path, backends: &[&str]) -> Option<Outcome> {
+    fs::write(file, program.to_string()).unwrap();
+
+    let mut first: Option<(&[&str], Run)> = None;
+
+    for flags in CONFIGURATIONS {
+        let Some(run) = run(command().args(flags).arg(file)) else {
+            return Some(Outcome {
+                failure: Failure::Timeout,
+                details: format!("{:?} ran for more than {:?}", flags, TIMEOUT),
+            });
+        };
+
+        if run.stderr.contains("panicked") {
+            return Some(Outcome {
+                failure: Failure::Panic,
+                details: format!("{:?} panicked:\n{}", flags, run.stderr),
+            });
+        }
+
+        if run.stderr.contains("previous error") {
+            return Some(Outcome {
+                failure: Failure::Rejected,
+                details: format!("{:?} rejected the program:\n{}", flags, run.stderr),
+            });
+        }
+
+        match &first {
+            None => first = Some((flags, run)),
+            Some((base_flags, base)) => {
+                if run.stdout != base.stdout || run.stderr != base.stderr || run.code != base.code {
+                    return Some(mismatch([(base_flags, base), (flags, &run)]));
+                }
+            }
+        }
+    }
+
+    let (base_flags, base) = first.unwrap();
+    let exe = file.with_extension("");
+
+    for (backend, level) in backends.iter().flat_map(|b| LEVELS.map(|l| (b, l))) {
+        let flags = [*backend, level];
+
+        let mut args = vec!["build", "-o", exe.to_str().unwrap()];
+        args.extend_from_slice(&flags);
+        let build = command().args(&args).arg(file).output().unwrap();
+        if !build.status.success() {
+            return Some(Outcome {
+                failure: Failure::Rejected,
+                details: format!(
+                    "{:?} failed to build:\n{}",
+                    flags,
+                    String::from_utf8_lossy(&build.stderr)
+                ),
+            });
+        }
+
+        let Some(run) = run(&mut Command::new(&exe)) else {
+            return Some(Outcome {
+                failure: Failure::Timeout,
+                details: format!("{:?} ran for more than {:?}", flags, TIMEOUT),
+            });
+        };
+
+        if run.stdout != base.stdout
+            || run.code != base.code
+            || error_summary(&run.stderr) != error_summary(&base.stderr)
+        {
+            return Some(mismatch([(base_flags, &base), (&flags, &run)]));
+        }
+    }
+
+    None
+}
+
+/* ===========================
+   Shrinker
+=========================== */
+
+/// Applies the `target`-th of all the single edits that make a program
+/// smaller: removing a statement, replacing an `if` or loop with one of
+/// its blocks, or replacing an expression with a literal or operand.
+struct Shrink {
+    target: usize,
+    seen: usize,
+}
+
+impl Shrink {
+    /// Whether the edit being visited is the one to apply.
+    fn hit(&mut self) -> bool {
+        self.seen += 1;
+        self.seen - 1 == self.target
+    }
+
+    fn done(&self) -> bool {
+        self.seen > self.target
+    }
+
+    fn program(&mut self, program: &mut Program) {
+        for func in &mut program.functions {
+            self.block(&mut func.body);
+        }
+    }
+
+    fn block(&mut self, block: &mut Vec<Stmt>) {
+        let mut i = 0;
+        while i < block.len() && !self.done() {
+            if self.hit() {
+                block.remove(i);
+                return;
+            }
+
+            let replacement = match &mut block[i] {
+                Stmt::If(condition, then_block, else_block) => {
+                    if self.hit() {
+                        Some(std::mem::take(then_block))
+                    } else if else_block.is_some() && self.hit() {
+                        else_block.take()
+                    } else if else_block.is_some() && self.hit() {
+                        *else_block = None;
+                        None
+                    } else {
+                        self.expr(condition);
+                        self.block(then_block);
+                        if let Some(b) = else_block {
+                            self.block(b);
+                        }
+                        None
+                    }
+                }
+
+                Stmt::While { limit, body, .. } => {
+                    if *limit > 1 && self.hit() {
+                        *limit = 1;
+                    } else {
+                        self.block(body);
+                    }
+                    None
+                }
+
+                Stmt::Let(_, e) | Stmt::Assign(_, e) | Stmt::Print(e) | Stmt::Return(e) => {
+                    self.expr(e);
+                    None
+                }
+
+                Stmt::Break | Stmt::Continue => None,
+            };
+
+            if let Some(statements) = replacement {
+                block.splice(i..=i, statements);
+                return;
+            }
+            i += 1;
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        if self.done() {
+            return;
+        }
+
+        if !matches!(expr, Expr::Int(0)) && self.hit() {
+            *expr = Expr::Int(0);
+            return;
+        }
+        if !matches!(expr, Expr::Int(0 | 1)) && self.hit() {
+            *expr = Expr::Int(1);
+            return;
+        }
+
+        match expr {
+            Expr::Binary(left, _, right) => {
+                if self.hit() {
+                    *expr = (**left).clone();
+                } else if self.hit() {
+                    *expr = (**right).clone();
+                } else {
+                    self.expr(left);
+                    self.expr(right);
+                }
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Int(_) | Expr::Str(_) | Expr::Var(_) => {}
+        }
+    }
+}
+
+/// Repeatedly apply the first edit that keeps the same kind of failure.
+fn shrink(
+    mut program: Program,
+    mut outcome: Outcome,
+    file: &Path,
+    backends: &[&str],
+) -> (Program, Outcome) {
+    let mut target = 0;
+
+    loop {
+        let mut candidate = program.clone();
+        let mut editor = Shrink { target, seen: 0 };
+        editor.program(&mut candidate);
+        if !editor.done() {
+            // Every edit was tried without success
+            return (program, outcome);
+        }
+
+        match check(&candidate, file, backends) {
+            Some(smaller) if smaller.failure == outcome.failure => {
+                program = candidate;
+                outcome = smaller;
+                target = 0;
+            }
+            _ => target += 1,
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[test]
+fn random_programs() {
+    let seed = env_or("NEXO_FUZZ_SEED", DEFAULT_SEED);
+    let cases = env_or("NEXO_FUZZ_CASES", DEFAULT_CASES);
+    let native_cases = env_or("NEXO_FUZZ_NATIVE_CASES", DEFAULT_NATIVE_CASES);
+    let native = native_backends();
+    if native.is_empty() {
+        eprintln!("skipping executables: no C compiler (`cc`) found");
+    }
+
+    let dir = out_dir("fuzz");
+    let file = dir.join("case.nx");
+
+    let mut rng = Rng::new(seed);
+
+    for case in 0..cases {
+        let program = generate(&mut rng);
+        let backends = if case < native_cases { native } else { &[] };
+
+        if let Some(outcome) = check(&program, &file, backends) {
+            let (program, outcome) = shrink(program, outcome, &file, backends);
+
+            let minimized = dir.join("minimized.nx");
+            fs::write(&minimized, program.to_string()).unwrap();
+
+            panic!(
+                "case {} of seed {:#x}: {:?} (saved to {})\n{}\n{}",
+                case,
+                seed,
+                outcome.failure,
+                minimized.display(),
+                program,
+                outcome.details
+            );
+        }
+    }
+}
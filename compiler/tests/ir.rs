@@ -0,0 +1,74 @@
+//! Golden tests for `build --emit=ir`. Every `tests/ir/foo.nx` is lowered
+//! from inside that directory, and the IR written must match `foo.ir`, or
+//! `foo.O1.ir` and `foo.O2.ir` when optimized. Each case shows one thing
+//! the optimizer does, or must not do.
+//!
+//! Run with `NEXO_BLESS=1` to write the expected files from the current
+//! output instead, then review the diff.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{cases, nexo, nexo_in, out_dir};
+
+/// Flags for each golden file, by extension.
+const LEVELS: [(&str, &str); 3] = [("-O0", "ir"), ("-O1", "O1.ir"), ("-O2", "O2.ir")];
+
+#[test]
+fn ir_matches_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let out_dir = out_dir("ir");
+    let bless = env::var_os("NEXO_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for case in cases(&dir) {
+        let name = case.file_name().unwrap().to_str().unwrap();
+
+        for (flag, extension) in LEVELS {
+            let output = out_dir.join(case.with_extension(extension).file_name().unwrap());
+            let build = nexo_in(&dir, &["build", "--emit=ir", flag, name, "-o", output.to_str().unwrap()]);
+            assert!(build.status.success(), "{} {}: {}", name, flag, String::from_utf8_lossy(&build.stderr));
+
+            let ir = fs::read_to_string(&output).unwrap();
+            let golden = case.with_extension(extension);
+            if bless {
+                fs::write(&golden, &ir).unwrap();
+                continue;
+            }
+
+            let expected = fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("{}: {}", golden.display(), e));
+            if ir != expected {
+                failures.push(format!("{} ({}):\n--- expected\n{}--- got\n{}", name, flag, expected, ir));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// `100 / d` does not change inside the loop, but it fails when `d` is 0,
+/// and moving it out would report that before the loop prints anything.
+#[test]
+fn failing_operation_stays_in_its_loop() {
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir/failing_operation_in_loop.nx");
+    let program = program.to_str().unwrap();
+
+    let output = out_dir("ir").join("failing_operation_in_loop.hoisted.ir");
+    let build = nexo(&["build", "--emit=ir", "-O2", program, "-o", output.to_str().unwrap()]);
+    assert!(build.status.success());
+    let ir = fs::read_to_string(&output).unwrap();
+    let print = ir.find("call print(").unwrap();
+    let div = ir.find("= div ").unwrap();
+    assert!(print < div, "the division left the loop:\n{}", ir);
+
+    for flags in [&["-O0"][..], &["-O1"], &["-O2"], &["--engine=tree"]] {
+        let run = nexo(&[flags, &[program]].concat());
+        assert_eq!(String::from_utf8_lossy(&run.stdout), "0\n1\n2\n6\n0\n", "{:?}", flags);
+        let stderr = String::from_utf8_lossy(&run.stderr);
+        assert_eq!(stderr.lines().next(), Some("error[E0103]: attempt to divide by zero"), "{:?}", flags);
+    }
+}
@@ -0,0 +1,105 @@
+//! Every example and corpus program built as a native executable, from C
+//! and from x86-64 assembly with the system's `cc`, at every optimization
+//! level, must behave like it does on the VM: print the same output, exit
+//! with the same code, and report a runtime error at the same place with
+//! the same message. Without `--emit`, the `-o` name picks what is built.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+mod common;
+
+use common::{command, error_summary, have_cc, native_backends, out_dir, programs};
+
+fn nexo(args: &[&str], program: &Path) -> Output {
+    command().args(args).arg(program).output().unwrap()
+}
+
+#[test]
+fn native_matches_vm() {
+    let backends = native_backends();
+    if backends.is_empty() {
+        eprintln!("skipping: no C compiler (`cc`) found");
+        return;
+    }
+
+    let out_dir = out_dir("native");
+    let mut checked = 0;
+
+    for program in programs() {
+        let vm = nexo(&[], &program);
+        if String::from_utf8_lossy(&vm.stderr).contains("previous error") {
+            // Programs with compile errors, like `bad.nx`
+            continue;
+        }
+
+        for (backend, level) in backends.iter().flat_map(|b| ["-O0", "-O1", "-O2"].map(|l| (b, l))) {
+            let flags = vec![*backend, level];
+            let exe = out_dir.join(program.file_stem().unwrap());
+
+            let mut args = vec!["build", "-o", exe.to_str().unwrap()];
+            args.extend_from_slice(&flags);
+            let build = nexo(&args, &program);
+            assert!(
+                build.status.success(),
+                "{}: building an executable failed:\n{}",
+                program.display(),
+                String::from_utf8_lossy(&build.stderr)
+            );
+
+            let native = Command::new(&exe).output().unwrap();
+            let context = format!("{} {:?}", program.display(), flags);
+            assert_eq!(
+                String::from_utf8_lossy(&native.stdout),
+                String::from_utf8_lossy(&vm.stdout),
+                "{}: output differs from the VM",
+                context
+            );
+            assert_eq!(native.status.code(), vm.status.code(), "{}: exit code", context);
+            assert_eq!(
+                error_summary(&String::from_utf8_lossy(&native.stderr)),
+                error_summary(&String::from_utf8_lossy(&vm.stderr)),
+                "{}: error",
+                context
+            );
+        }
+
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
+
+#[test]
+fn output_name_chooses_what_is_built() {
+    let out_dir = out_dir("native_outputs");
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples/hello.nx");
+
+    let text = [("hello.ir", "fn main() {\n"), ("hello.c", "/* Nexo runtime"), ("hello.s", "# Nexo runtime")];
+    for (name, start) in text {
+        let output = out_dir.join(name);
+        let build = nexo(&["build", "-o", output.to_str().unwrap()], &program);
+        assert!(build.status.success(), "{}: {}", name, String::from_utf8_lossy(&build.stderr));
+        let contents = fs::read_to_string(&output).unwrap();
+        assert!(contents.starts_with(start), "{}: {:?}", name, contents.lines().next());
+    }
+
+    let nxc = out_dir.join("hello.nxc");
+    assert!(nexo(&["build", "-o", nxc.to_str().unwrap()], &program).status.success());
+    assert!(fs::read(&nxc).unwrap().starts_with(b"NXC\0"));
+    assert_eq!(nexo(&["run"], &nxc).stdout, b"Hello, World!\n");
+
+    if !have_cc() {
+        eprintln!("skipping executables: no C compiler (`cc`) found");
+        return;
+    }
+
+    // Any other name is an executable, not bytecode
+    for name in ["hello", "hello.exe", "a.out"] {
+        let exe = out_dir.join(name);
+        let build = nexo(&["build", "-o", exe.to_str().unwrap()], &program);
+        assert!(build.status.success(), "{}: {}", name, String::from_utf8_lossy(&build.stderr));
+        assert_eq!(Command::new(&exe).output().unwrap().stdout, b"Hello, World!\n", "{}", name);
+    }
+}
@@ -0,0 +1,185 @@
+//! The `.nxc` bytecode file format: every example and corpus program runs
+//! the same from a built file as from source, and files that are corrupted,
+//! from another format version or inconsistent are rejected with `E0100`
+//! before anything runs.
+
+use std::fs;
+use std::process::Output;
+
+mod common;
+
+use common::{nexo, out_dir, programs};
+
+#[test]
+fn built_files_run_like_their_source() {
+    let mut checked = 0;
+
+    for program in programs() {
+        let source = program.to_str().unwrap();
+        let from_source = nexo(&["run", source]);
+        if String::from_utf8_lossy(&from_source.stderr).contains("previous error") {
+            // Programs with compile errors, like `bad.nx`
+            continue;
+        }
+
+        for flags in [&[][..], &["-O"]] {
+            let nxc = out_dir("nxc").join(program.with_extension("nxc").file_name().unwrap());
+            let nxc = nxc.to_str().unwrap();
+
+            let mut args = vec!["build", source, "-o", nxc];
+            args.extend_from_slice(flags);
+            let build = nexo(&args);
+            assert!(build.status.success(), "{}: {}", source, String::from_utf8_lossy(&build.stderr));
+
+            let loaded = nexo(&["run", nxc]);
+            let context = format!("{} {:?}", source, flags);
+            assert_eq!(loaded.stdout, nexo(&[&["run", source], flags].concat()).stdout, "{}", context);
+            assert_eq!(loaded.status.code(), from_source.status.code(), "{}", context);
+
+            // Saving and loading keeps every instruction, constant and span
+            let mut disasm = vec!["disasm", source];
+            disasm.extend_from_slice(flags);
+            assert_eq!(
+                String::from_utf8_lossy(&nexo(&["disasm", nxc]).stdout),
+                String::from_utf8_lossy(&nexo(&disasm).stdout),
+                "{}: disassembly differs",
+                context
+            );
+        }
+
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
+
+/* ===========================
+   Malformed files
+=========================== */
+
+const PUSH_CONST: u8 = 1;
+const PUSH_VOID: u8 = 2;
+const CALL: u8 = 12;
+const CALL_BUILTIN: u8 = 13;
+const RETURN: u8 = 14;
+const POP: u8 = 15;
+const JUMP: u8 = 16;
+
+fn u32(n: u32) -> Vec<u8> {
+    n.to_le_bytes().to_vec()
+}
+
+fn string(s: &str) -> Vec<u8> {
+    [u32(s.len() as u32), s.as_bytes().to_vec()].concat()
+}
+
+/// A file for `fn main() { print("hi"); }` laid out as documented in
+/// `src/vm/nxc.rs`, with `code` as its instructions and `main` starting at
+/// `entry`.
+fn file(version: u16, entry: u32, code: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = b"NXC\0".to_vec();
+    out.extend(version.to_le_bytes());
+    out.extend(string("hi.nx"));
+
+    // One string constant
+    out.extend(u32(1));
+    out.push(1);
+    out.extend(string("hi"));
+
+    // `main`, with no parameters
+    out.extend(u32(1));
+    out.extend(string("main"));
+    out.extend(u32(0));
+    out.extend(u32(entry));
+
+    out.extend(u32(code.len() as u32));
+    for instruction in code {
+        out.extend(instruction);
+    }
+
+    // Every instruction at 1:1
+    out.extend(u32(1));
+    out.extend(u32(0));
+    for field in [0, 2, 1, 1] {
+        out.extend(u32(field));
+    }
+    out
+}
+
+fn print_hi() -> Vec<Vec<u8>> {
+    vec![
+        [vec![PUSH_CONST], u32(0)].concat(),
+        vec![CALL_BUILTIN, 0],
+        vec![POP],
+        vec![PUSH_VOID],
+        vec![RETURN],
+    ]
+}
+
+fn load(name: &str, bytes: &[u8]) -> Output {
+    let path = out_dir("nxc").join(format!("{}.nxc", name));
+    fs::write(&path, bytes).unwrap();
+    nexo(&["run", path.to_str().unwrap()])
+}
+
+#[test]
+fn well_formed_file_runs() {
+    let output = load("valid", &file(1, 0, &print_hi()));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+    assert!(output.status.success());
+}
+
+#[test]
+fn malformed_files_are_rejected() {
+    let valid = file(1, 0, &print_hi());
+    let with_instruction = |index: usize, instruction: Vec<u8>| {
+        let mut code = print_hi();
+        code[index] = instruction;
+        file(1, 0, &code)
+    };
+
+    let cases = [
+        ("bad_magic", [b"NXB\0".to_vec(), valid[4..].to_vec()].concat(), "not a nexo bytecode file"),
+        (
+            "version",
+            file(2, 0, &print_hi()),
+            "unsupported format version 2 (this nexo reads version 1); rebuild it from source",
+        ),
+        ("truncated", valid[..valid.len() - 3].to_vec(), "unexpected end of file"),
+        (
+            "trailing",
+            [valid.clone(), vec![0]].concat(),
+            "unexpected data after the end of the program",
+        ),
+        ("entry", file(1, 5, &print_hi()), "function `main` starts outside the code"),
+        ("unknown_opcode", with_instruction(2, vec![200]), "unknown opcode 200"),
+        ("unknown_builtin", with_instruction(1, vec![CALL_BUILTIN, 99]), "unknown built-in function 99"),
+        (
+            "jump_target",
+            with_instruction(2, [vec![JUMP], u32(5)].concat()),
+            "instruction 2 (Jump(5)) refers outside the program",
+        ),
+        (
+            "call_index",
+            with_instruction(1, [vec![CALL], u32(1)].concat()),
+            "instruction 1 (Call(1)) refers outside the program",
+        ),
+        (
+            "constant_index",
+            with_instruction(0, [vec![PUSH_CONST], u32(1)].concat()),
+            "instruction 0 (PushConst(1)) refers outside the program",
+        ),
+    ];
+
+    for (name, bytes, message) in cases {
+        let output = load(name, &bytes);
+        let path = out_dir("nxc").join(format!("{}.nxc", name));
+        let expected = format!("error[E0100]: cannot load `{}`: {}", path.display(), message);
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr.lines().next(), Some(expected.as_str()), "{}", name);
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        assert!(output.stdout.is_empty(), "{}: ran anyway", name);
+    }
+}
@@ -0,0 +1,123 @@
+//! The bytecode verifier: hand-written `.nxs` programs that assemble but
+//! are unsafe to run must be rejected with `E0100`, pointing at the bad
+//! instruction, before any of them runs.
+
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+mod common;
+
+use common::{nexo, write_file};
+
+fn write_assembly(name: &str, text: &str) -> PathBuf {
+    write_file("verify", &format!("{}.nxs", name), text)
+}
+
+fn run_assembly(name: &str, text: &str) -> Output {
+    nexo(&[Path::new("run"), &write_assembly(name, text)])
+}
+
+/// `main` calls `add`, which takes two arguments, with one.
+fn wrong_arity() -> String {
+    program(
+        "    push_int 3\n    call add\n    return\n",
+        "fn add 2\n    .loc 2:1 2..3\n    load 0\n    load 1\n    add\n    return\n",
+    )
+}
+
+const WRONG_ARITY: &str = "invalid bytecode in `main`: instruction 4 (`Call(1)`) calls `add` with 1 value on the stack, but it takes 2 arguments";
+
+/// `main` prints 1 first, so a program that starts running is noticed.
+fn program(main: &str, others: &str) -> String {
+    format!(
+        ".source \"v.nx\"\n\nfn main 0\n    .loc 1:1 0..1\n    push_int 1\n    builtin print\n    pop\n{}\n{}",
+        main, others
+    )
+}
+
+#[test]
+fn well_formed_assembly_runs() {
+    let text = program("    call twice\n    pop\n    push_void\n    return\n", "fn twice 0\n    .loc 2:1 2..3\n    push_int 2\n    builtin print\n    return\n");
+    let output = run_assembly("valid", &text);
+
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+}
+
+#[test]
+fn unsafe_bytecode_is_rejected() {
+    let cases = [
+        (
+            "jump_outside_function",
+            program("    jump elsewhere\n", "fn other 0\n    .loc 2:1 2..3\nelsewhere:\n    push_void\n    return\n"),
+            "invalid bytecode in `main`: instruction 3 (`Jump(4)`) jumps outside the function",
+        ),
+        (
+            "stack_underflow",
+            program("    push_int 2\n    add\n    return\n", ""),
+            "invalid bytecode in `main`: instruction 4 (`Add`) pops from an empty stack",
+        ),
+        (
+            "local_outside_frame",
+            program("    load 0\n    return\n", ""),
+            "invalid bytecode in `main`: instruction 3 (`LoadLocal(0)`) reads a local outside the frame",
+        ),
+        (
+            "heights_differ_at_merge",
+            program(
+                "    push_int 0\n    jump_if_false skip\n    push_int 5\nskip:\n    push_void\n    return\n",
+                "",
+            ),
+            "invalid bytecode in `main`: instruction 6 (`PushVoid`) is reached with different stack heights (0 and 1)",
+        ),
+        ("wrong_arity", wrong_arity(), WRONG_ARITY),
+        (
+            "missing_return",
+            program("    push_void\n    pop\n", ""),
+            "invalid bytecode in `main`: instruction 4 (`Pop`) runs past the end of the function without returning",
+        ),
+        (
+            "missing_return_before_next_function",
+            program("    push_void\n", "fn other 0\n    .loc 2:1 2..3\n    push_void\n    return\n"),
+            "invalid bytecode in `main`: instruction 3 (`PushVoid`) runs past the end of the function without returning",
+        ),
+    ];
+
+    for (name, text, message) in cases {
+        let output = run_assembly(name, &text);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let expected = format!("error[E0100]: {}", message);
+
+        assert_eq!(stderr.lines().next(), Some(expected.as_str()), "{}", name);
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        assert!(output.stdout.is_empty(), "{}: ran before being rejected", name);
+    }
+}
+
+#[test]
+fn unknown_callee_is_rejected_when_assembling() {
+    let output = run_assembly("unknown_callee", &program("    call missing\n    return\n", ""));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(stderr.lines().next(), Some("error[E0100]: undefined function `missing`"));
+    assert!(output.stdout.is_empty());
+}
+
+/// Calls are resolved and their arity checked by the analyzer for source
+/// files, and by the verifier for bytecode from anywhere else: a `.nxc`
+/// file is written as it was assembled, and checked when it is run.
+#[test]
+fn wrong_arity_is_rejected_in_bytecode_files() {
+    let nxs = write_assembly("wrong_arity_built", &wrong_arity());
+    let nxc = nxs.with_extension("nxc");
+    let build = nexo(&[Path::new("build"), &nxs, Path::new("-o"), &nxc]);
+    assert!(build.status.success(), "{}", String::from_utf8_lossy(&build.stderr));
+
+    let output = nexo(&[Path::new("run"), &nxc]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = format!("error[E0100]: {}", WRONG_ARITY);
+
+    assert_eq!(stderr.lines().next(), Some(expected.as_str()));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}